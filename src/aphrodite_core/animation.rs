@@ -0,0 +1,306 @@
+use std::{
+    io::Cursor,
+    time::{Duration, Instant},
+};
+
+use color_eyre::eyre::{bail, eyre, Result};
+use image::{
+    codecs::{gif::GifDecoder, png::PngDecoder, webp::WebPDecoder},
+    AnimationDecoder, ImageFormat, RgbaImage,
+};
+use wgpu::util::DeviceExt;
+
+use super::engine::{EngineCore, SimpleImage, Vertex};
+use super::image_scene::fit_quad;
+use super::texture;
+use crate::config::FitMode;
+
+/// Browsers treat delays this short as "as fast as possible" and slow them down to
+/// [`DEFAULT_DELAY`], many GIFs in the wild rely on that.
+const MIN_DELAY: Duration = Duration::from_millis(20);
+const DEFAULT_DELAY: Duration = Duration::from_millis(100);
+
+/// Fully composited frames of an animated image, in decoding order.
+#[derive(Clone)]
+pub struct FrameSequence {
+    pub frames: Vec<(RgbaImage, Duration)>,
+    /// How many times the animation is played, `None` loops forever.
+    pub plays: Option<u32>,
+}
+
+impl FrameSequence {
+    /// Decodes an animated GIF, PNG or WebP. Frame disposal and blending are applied by the
+    /// decoders, so every frame is a full canvas. Still images become a single frame.
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        if !Self::is_animated(bytes) {
+            return Ok(Self::still(image::load_from_memory(bytes)?));
+        }
+
+        let (frames, plays) = match image::guess_format(bytes)? {
+            ImageFormat::Gif => {
+                let decoder = GifDecoder::new(Cursor::new(bytes))?;
+                (Self::collect(decoder)?, gif_plays(bytes))
+            }
+            ImageFormat::Png => {
+                let decoder = PngDecoder::new(Cursor::new(bytes))?.apng();
+                (Self::collect(decoder)?, apng_plays(bytes))
+            }
+            ImageFormat::WebP => {
+                let decoder = WebPDecoder::new(Cursor::new(bytes))?;
+                (Self::collect(decoder)?, webp_plays(bytes))
+            }
+            format => bail!("{format:?} images can not be animated"),
+        };
+
+        Ok(Self { frames, plays })
+    }
+
+    /// A single frame showing `img`, played once.
+    pub fn still(img: image::DynamicImage) -> Self {
+        Self {
+            frames: vec![(img.into_rgba8(), DEFAULT_DELAY)],
+            plays: Some(1),
+        }
+    }
+
+    /// Checks the headers for an animation, without decoding any pixels.
+    pub fn is_animated(bytes: &[u8]) -> bool {
+        match image::guess_format(bytes) {
+            Ok(ImageFormat::Gif) => true,
+            // acTL has to come before the image data
+            Ok(ImageFormat::Png) => {
+                let header = find(bytes, b"IDAT").map_or(bytes, |pos| &bytes[..pos]);
+                find(header, b"acTL").is_some()
+            }
+            // The animation flag of the VP8X chunk, which is the first chunk when present
+            Ok(ImageFormat::WebP) => {
+                bytes.get(12..16) == Some(b"VP8X") && bytes.get(20).is_some_and(|f| f & 0x02 != 0)
+            }
+            _ => false,
+        }
+    }
+
+    fn collect<'a>(decoder: impl AnimationDecoder<'a>) -> Result<Vec<(RgbaImage, Duration)>> {
+        let frames = decoder
+            .into_frames()
+            .map(|frame| {
+                let frame = frame?;
+                let mut delay = Duration::from(frame.delay());
+                if delay < MIN_DELAY {
+                    delay = DEFAULT_DELAY;
+                }
+                Ok((frame.into_buffer(), delay))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        if frames.is_empty() {
+            bail!("animation has no frames");
+        }
+
+        Ok(frames)
+    }
+}
+
+/// Position right after the first occurrence of `tag`.
+fn find(bytes: &[u8], tag: &[u8]) -> Option<usize> {
+    bytes
+        .windows(tag.len())
+        .position(|w| w == tag)
+        .map(|pos| pos + tag.len())
+}
+
+/// Reads the loop count of the NETSCAPE2.0 application extension.
+///
+/// The `image` decoders do not expose loop counts. For GIF a missing extension means the
+/// animation plays once, a count of zero means it loops forever and any other count is the
+/// number of extra loops.
+fn gif_plays(bytes: &[u8]) -> Option<u32> {
+    let loop_count = find(bytes, b"\x21\xff\x0bNETSCAPE2.0\x03\x01")
+        .and_then(|start| bytes.get(start..start + 2))
+        .map(|count| u16::from_le_bytes([count[0], count[1]]));
+
+    match loop_count {
+        None => Some(1),
+        Some(0) => None,
+        Some(n) => Some(n as u32 + 1),
+    }
+}
+
+/// Reads `num_plays` of the acTL chunk, which follows `num_frames`. Zero loops forever.
+fn apng_plays(bytes: &[u8]) -> Option<u32> {
+    let plays = find(bytes, b"acTL")
+        .and_then(|start| bytes.get(start + 4..start + 8))
+        .map_or(0, |plays| u32::from_be_bytes([plays[0], plays[1], plays[2], plays[3]]));
+
+    (plays != 0).then_some(plays)
+}
+
+/// Reads the loop count of the ANIM chunk, after its size and background color. Zero loops
+/// forever.
+fn webp_plays(bytes: &[u8]) -> Option<u32> {
+    let plays = find(bytes, b"ANIM")
+        .and_then(|start| bytes.get(start + 8..start + 10))
+        .map_or(0, |count| u16::from_le_bytes([count[0], count[1]]));
+
+    (plays != 0).then_some(plays as u32)
+}
+
+/// Timing of an animation: which frame is showing and when the next one is due.
+pub struct Playback {
+    delays: Vec<Duration>,
+    plays: Option<u32>,
+
+    current: usize,
+    frame_started: Option<Instant>,
+    finished_plays: u32,
+    paused: bool,
+}
+
+impl Playback {
+    pub fn new(delays: Vec<Duration>, plays: Option<u32>) -> Self {
+        Self {
+            delays,
+            plays,
+            current: 0,
+            frame_started: None,
+            finished_plays: 0,
+            paused: false,
+        }
+    }
+
+    /// Index of the frame to show.
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    /// Whether there are frames left to show.
+    pub fn is_playing(&self) -> bool {
+        !self.paused
+            && self.delays.len() > 1
+            && self.plays.is_none_or(|plays| self.finished_plays < plays)
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+        // The current frame gets its full delay again once resumed
+        self.frame_started = None;
+    }
+
+    /// When the current frame is over, `None` before the first update.
+    pub fn next_update(&self) -> Option<Instant> {
+        Some(self.frame_started? + self.delays[self.current])
+    }
+
+    /// Advances to the frame that should be visible at `now`. Returns whether it changed.
+    pub fn update(&mut self, now: Instant) -> bool {
+        let Some(mut started) = self.frame_started else {
+            self.frame_started = Some(now);
+            return false;
+        };
+
+        // Skipped frames (e.g. while the output was off) are caught up on, so the
+        // animation stays in sync with the wall clock
+        let mut changed = false;
+        while self.is_playing() && now.duration_since(started) >= self.delays[self.current] {
+            started += self.delays[self.current];
+
+            if self.current + 1 == self.delays.len() {
+                self.finished_plays += 1;
+                if !self.is_playing() {
+                    // Stay on the last frame once all plays are done
+                    break;
+                }
+                self.current = 0;
+            } else {
+                self.current += 1;
+            }
+            changed = true;
+        }
+        self.frame_started = Some(started);
+
+        changed
+    }
+}
+
+/// Plays a [`FrameSequence`] from textures uploaded once, so nothing is decoded per frame.
+pub struct AnimatedScene {
+    frames: Vec<(texture::Texture, wgpu::BindGroup)>,
+    playback: Playback,
+    vertex_buffer: wgpu::Buffer,
+    pub image_size: (u32, u32),
+    fit: FitMode,
+    pub background: wgpu::Color,
+}
+
+impl AnimatedScene {
+    pub fn new(
+        core: &EngineCore,
+        sequence: FrameSequence,
+        fit: FitMode,
+        background: wgpu::Color,
+    ) -> Result<Self> {
+        let image_size = sequence.frames[0].0.dimensions();
+
+        let sampler = SimpleImage::create_sampler(core, fit);
+
+        let mut frames = Vec::with_capacity(sequence.frames.len());
+        let mut delays = Vec::with_capacity(sequence.frames.len());
+        for (i, (buffer, delay)) in sequence.frames.into_iter().enumerate() {
+            let img = image::DynamicImage::ImageRgba8(buffer);
+            let label = format!("Animation frame {i}");
+            let texture =
+                texture::Texture::from_image(core, &img, Some(&label))
+                    .map_err(|e| eyre!(e))?;
+            let bind_group = SimpleImage::create_bind_group(core, &texture, &sampler);
+
+            frames.push((texture, bind_group));
+            delays.push(delay);
+        }
+
+        let vertex_buffer = core
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Animation quad"),
+                contents: bytemuck::cast_slice(&[Vertex::default(); 6]),
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            });
+
+        Ok(Self {
+            frames,
+            playback: Playback::new(delays, sequence.plays),
+            vertex_buffer,
+            image_size,
+            fit,
+            background,
+        })
+    }
+
+    pub fn resize(&self, core: &EngineCore, width: u32, height: u32) {
+        let vertices = fit_quad(self.fit, self.image_size, (width, height));
+        core.queue
+            .write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(&vertices));
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playback.is_playing()
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.playback.set_paused(paused);
+    }
+
+    pub fn next_update(&self) -> Option<Instant> {
+        self.playback.next_update()
+    }
+
+    pub fn update(&mut self, now: Instant) -> bool {
+        self.playback.update(now)
+    }
+
+    pub fn draw<'a>(&'a self, core: &'a EngineCore, pass: &mut wgpu::RenderPass<'a>) {
+        pass.set_pipeline(&core.image_render_pipeline);
+        pass.set_bind_group(0, &self.frames[self.playback.current()].1, &[]);
+        pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        pass.draw(0..6, 0..1);
+    }
+}
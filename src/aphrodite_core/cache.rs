@@ -0,0 +1,170 @@
+//! Disk cache of still images decoded and shrunk for an output, in
+//! `$XDG_CACHE_HOME/aphrodite`. Entries are raw RGBA, so a later start reads them back in one
+//! go instead of decoding the file again.
+
+use std::{
+    collections::hash_map::DefaultHasher,
+    env,
+    fs::{self, File},
+    hash::{Hash, Hasher},
+    io::Write,
+    path::PathBuf,
+    process,
+    sync::atomic::{AtomicU64, Ordering},
+    time::SystemTime,
+};
+
+use color_eyre::eyre::{Result, WrapErr};
+use image::RgbaImage;
+
+use crate::config::{Downscale, FitMode};
+
+/// Start of every entry, followed by the width and height as little endian `u32`s.
+const MAGIC: &[u8; 8] = b"APHRC\0\0\x01";
+const HEADER_LEN: usize = MAGIC.len() + 8;
+/// The least recently used entries are removed once the cache grows past this many bytes.
+const MAX_SIZE: u64 = 1 << 30;
+
+/// Tells the temporary files of the writers in this process apart.
+static NEXT_WRITER: AtomicU64 = AtomicU64::new(0);
+
+pub struct ImageCache {
+    dir: PathBuf,
+}
+
+impl ImageCache {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /// The cache in `$XDG_CACHE_HOME/aphrodite`, `None` when neither that nor `$HOME` is set.
+    pub fn open() -> Option<Self> {
+        let base = match env::var_os("XDG_CACHE_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(env::var_os("HOME")?).join(".cache"),
+        };
+
+        Some(Self::new(base.join("aphrodite")))
+    }
+
+    /// Name of the entry for the contents of a file prepared for a `target` of that many
+    /// pixels. The hash is not stable across Rust releases, which at worst decodes the file
+    /// once more.
+    pub fn key(bytes: &[u8], fit: FitMode, downscale: Downscale, target: (u32, u32)) -> String {
+        let mut hasher = DefaultHasher::new();
+        bytes.hash(&mut hasher);
+        fit.hash(&mut hasher);
+        downscale.hash(&mut hasher);
+        target.hash(&mut hasher);
+
+        format!("{:016x}-{}x{}.rgba", hasher.finish(), target.0, target.1)
+    }
+
+    /// The image stored under `key`, `None` when there is none or it is damaged.
+    pub fn load(&self, key: &str) -> Option<RgbaImage> {
+        let path = self.dir.join(key);
+        let mut data = fs::read(&path).ok()?;
+        if data.len() < HEADER_LEN || &data[..MAGIC.len()] != MAGIC {
+            return None;
+        }
+        let size = |at: usize| u32::from_le_bytes(data[at..at + 4].try_into().unwrap());
+        let (width, height) = (size(MAGIC.len()), size(MAGIC.len() + 4));
+        // Cut short or left over from a different format
+        if (data.len() - HEADER_LEN) as u64 != width as u64 * height as u64 * 4 {
+            return None;
+        }
+
+        // Keeps it from being pruned as the least recently used entry
+        if let Ok(file) = File::options().append(true).open(&path) {
+            let _ = file.set_modified(SystemTime::now());
+        }
+
+        data.drain(..HEADER_LEN);
+        RgbaImage::from_raw(width, height, data)
+    }
+
+    /// Stores an image under `key`, and makes room for it by removing old entries.
+    pub fn store(&self, key: &str, image: &RgbaImage) -> Result<()> {
+        fs::create_dir_all(&self.dir)
+            .wrap_err_with(|| format!("failed to create {}", self.dir.display()))?;
+
+        // Written next to the entry first, so a crash never leaves half of one behind. Every
+        // writer gets a file of its own, outputs showing the same image store it at once.
+        let path = self.dir.join(key);
+        let writer = NEXT_WRITER.fetch_add(1, Ordering::Relaxed);
+        let partial = path.with_extension(format!("{}-{writer}.partial", process::id()));
+        let mut file = File::options()
+            .write(true)
+            .create_new(true)
+            .open(&partial)
+            .wrap_err_with(|| format!("failed to create {}", partial.display()))?;
+        let written = (|| {
+            file.write_all(MAGIC)?;
+            file.write_all(&image.width().to_le_bytes())?;
+            file.write_all(&image.height().to_le_bytes())?;
+            file.write_all(image.as_raw())?;
+            fs::rename(&partial, &path)
+        })();
+        if let Err(err) = written {
+            let _ = fs::remove_file(&partial);
+            return Err(err).wrap_err_with(|| format!("failed to write {}", path.display()));
+        }
+
+        self.prune(MAX_SIZE)
+    }
+
+    /// Removes the least recently used entries until the cache fits into `max_size` bytes.
+    /// Entries other writers are still writing are left alone.
+    fn prune(&self, max_size: u64) -> Result<()> {
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            if path
+                .extension()
+                .is_some_and(|extension| extension == "partial")
+            {
+                continue;
+            }
+            let metadata = entry.metadata()?;
+            if metadata.is_file() {
+                entries.push((metadata.modified()?, metadata.len(), path));
+            }
+        }
+
+        let mut size: u64 = entries.iter().map(|(_, len, _)| len).sum();
+        entries.sort();
+        for (_, len, path) in entries {
+            if size <= max_size {
+                break;
+            }
+            fs::remove_file(&path)
+                .wrap_err_with(|| format!("failed to remove {}", path.display()))?;
+            size -= len;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prune_keeps_partial_entries() {
+        let dir = env::temp_dir().join(format!("aphrodite-cache-{}", process::id()));
+        let cache = ImageCache::new(dir.clone());
+        cache.store("entry.rgba", &RgbaImage::new(2, 2)).unwrap();
+        let partial = dir.join("other.1-0.partial");
+        fs::write(&partial, [0; 64]).unwrap();
+
+        cache.prune(0).unwrap();
+        let left: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(left, [partial]);
+    }
+}
@@ -0,0 +1,64 @@
+use color_eyre::eyre::Result;
+use smithay_client_toolkit::{
+    delegate_output, delegate_registry,
+    output::{OutputHandler, OutputInfo, OutputState},
+    registry::{ProvidesRegistryState, RegistryState},
+    registry_handlers,
+};
+use wayland_client::{
+    globals::registry_queue_init, protocol::wl_output::WlOutput, Connection, QueueHandle,
+};
+
+/// Short lived state used to learn about the outputs before any surface is created.
+struct OutputProbe {
+    registry_state: RegistryState,
+    output_state: OutputState,
+}
+
+/// Lists the outputs advertised by the compositor together with their info.
+pub fn list_outputs(conn: &Connection) -> Result<Vec<(WlOutput, OutputInfo)>> {
+    let (globals, mut event_queue) = registry_queue_init::<OutputProbe>(conn)?;
+    let qh = event_queue.handle();
+
+    let mut probe = OutputProbe {
+        registry_state: RegistryState::new(&globals),
+        output_state: OutputState::new(&globals, &qh),
+    };
+
+    // First roundtrip binds the outputs, the second one collects their info.
+    event_queue.roundtrip(&mut probe)?;
+    event_queue.roundtrip(&mut probe)?;
+
+    Ok(probe
+        .output_state
+        .outputs()
+        .filter_map(|output| {
+            let info = probe.output_state.info(&output)?;
+            Some((output, info))
+        })
+        .collect())
+}
+
+impl OutputHandler for OutputProbe {
+    fn output_state(&mut self) -> &mut OutputState {
+        &mut self.output_state
+    }
+
+    fn new_output(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _output: WlOutput) {}
+
+    fn update_output(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _output: WlOutput) {}
+
+    fn output_destroyed(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _output: WlOutput) {
+    }
+}
+
+impl ProvidesRegistryState for OutputProbe {
+    fn registry(&mut self) -> &mut RegistryState {
+        &mut self.registry_state
+    }
+
+    registry_handlers!(OutputState);
+}
+
+delegate_output!(OutputProbe);
+delegate_registry!(OutputProbe);
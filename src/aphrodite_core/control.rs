@@ -0,0 +1,391 @@
+//! Server side of the control socket, see [`crate::ipc`] for the protocol.
+
+use std::{
+    fs,
+    io::{self, Read, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use color_eyre::eyre::{bail, eyre, Result, WrapErr};
+use smithay_client_toolkit::reexports::calloop::{
+    generic::Generic, Interest, LoopHandle, Mode, PostAction,
+};
+use wayland_client::QueueHandle;
+
+use super::engine::{EngineShell, SceneType};
+use crate::config::{FitMode, SceneKind, WallpaperConfig};
+use crate::ipc::{self, OutputStatus, Request, Response};
+
+/// Requests are a single line, anything longer is not one of ours.
+const MAX_REQUEST_LEN: usize = 64 * 1024;
+
+/// Clients that stop reading their response are dropped after this long.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The listening socket, removed again when dropped.
+pub struct ControlSocket {
+    path: PathBuf,
+}
+
+impl ControlSocket {
+    /// Starts accepting requests on the event loop of the shell.
+    pub fn bind(
+        loop_handle: &LoopHandle<'static, EngineShell>,
+        qh: &QueueHandle<EngineShell>,
+    ) -> Result<Self> {
+        let path = ipc::socket_path()?;
+        if path.exists() {
+            if UnixStream::connect(&path).is_ok() {
+                bail!("another instance is listening on {}", path.display());
+            }
+            // Left behind by a daemon that did not exit cleanly
+            fs::remove_file(&path)
+                .wrap_err_with(|| format!("failed to remove {}", path.display()))?;
+        }
+
+        let listener = UnixListener::bind(&path)
+            .wrap_err_with(|| format!("failed to bind {}", path.display()))?;
+        listener.set_nonblocking(true)?;
+
+        let qh = qh.clone();
+        let source = Generic::new(listener, Interest::READ, Mode::Level);
+        loop_handle
+            .insert_source(source, move |_, listener, shell| {
+                loop {
+                    match listener.accept() {
+                        Ok((stream, _)) => {
+                            if let Err(err) = accept(shell, &qh, stream) {
+                                log::warn!("Failed to accept a control connection: {err}");
+                            }
+                        }
+                        Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                        Err(err) => {
+                            log::warn!("Failed to accept a control connection: {err}");
+                            break;
+                        }
+                    }
+                }
+                Ok(PostAction::Continue)
+            })
+            .map_err(|err| err.error)?;
+
+        log::info!("Listening for control requests on {}", path.display());
+        Ok(Self { path })
+    }
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Reads the request of a client as it arrives, without blocking the event loop.
+fn accept(
+    shell: &mut EngineShell,
+    qh: &QueueHandle<EngineShell>,
+    stream: UnixStream,
+) -> Result<()> {
+    stream.set_nonblocking(true)?;
+
+    let qh = qh.clone();
+    let mut request = Vec::new();
+    let source = Generic::new(stream, Interest::READ, Mode::Level);
+    shell
+        .loop_handle
+        .insert_source(source, move |_, stream, shell| {
+            let mut chunk = [0; 4096];
+            loop {
+                match stream.read(&mut chunk) {
+                    // The client hung up, answer whatever it sent
+                    Ok(0) => break,
+                    Ok(len) => {
+                        request.extend_from_slice(&chunk[..len]);
+                        if request.contains(&b'\n') || request.len() > MAX_REQUEST_LEN {
+                            break;
+                        }
+                    }
+                    Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                        return Ok(PostAction::Continue)
+                    }
+                    Err(err) => {
+                        log::warn!("Failed to read a control request: {err}");
+                        return Ok(PostAction::Remove);
+                    }
+                }
+            }
+
+            let line = request.split(|&byte| byte == b'\n').next().unwrap_or(&[]);
+            let response = match serde_json::from_slice::<Request>(line) {
+                Ok(request) => {
+                    log::debug!("Control request: {request:?}");
+                    shell.handle_request(&qh, request)
+                }
+                Err(err) => Response::Error {
+                    message: format!("invalid request: {err}"),
+                },
+            };
+
+            if let Err(err) = respond(stream, &response) {
+                log::warn!("Failed to answer a control request: {err}");
+            }
+            Ok(PostAction::Remove)
+        })
+        .map_err(|err| eyre!(err.error))?;
+
+    Ok(())
+}
+
+fn respond(stream: &mut UnixStream, response: &Response) -> io::Result<()> {
+    let mut line = serde_json::to_vec(response)?;
+    line.push(b'\n');
+
+    stream.set_nonblocking(false)?;
+    stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+    stream.write_all(&line)
+}
+
+impl EngineShell {
+    pub fn handle_request(&mut self, qh: &QueueHandle<Self>, request: Request) -> Response {
+        let result = match request {
+            Request::Set {
+                output,
+                path,
+                scene,
+                fit,
+            } => self.set_wallpaper(qh, output.as_deref(), path, scene, fit),
+            Request::Pause { output } => self.set_paused(qh, output.as_deref(), true),
+            Request::Resume { output } => self.set_paused(qh, output.as_deref(), false),
+            Request::Next { output } => self.step_wallpaper(qh, output.as_deref(), 1),
+            Request::Previous { output } => self.step_wallpaper(qh, output.as_deref(), -1),
+            Request::Query => return self.query(),
+            Request::Quit => {
+                log::info!("Exiting on request");
+                self.exit = true;
+                Ok(())
+            }
+        };
+
+        match result {
+            Ok(()) => Response::Ok,
+            Err(err) => Response::Error {
+                message: format!("{err:#}"),
+            },
+        }
+    }
+
+    /// Indices of the named output, or of all outputs.
+    fn select_outputs(&self, name: Option<&str>) -> Result<Vec<usize>> {
+        let indices: Vec<usize> = (0..self.outputs.len())
+            .filter(|&index| name.is_none() || self.output_name(index).as_deref() == name)
+            .collect();
+
+        match name {
+            Some(name) if indices.is_empty() => bail!("output `{name}` not found"),
+            _ => Ok(indices),
+        }
+    }
+
+    fn output_name(&self, index: usize) -> Option<String> {
+        self.output_state
+            .info(&self.outputs[index].output)
+            .and_then(|info| info.name)
+    }
+
+    fn set_wallpaper(
+        &mut self,
+        qh: &QueueHandle<Self>,
+        output: Option<&str>,
+        path: PathBuf,
+        scene: Option<String>,
+        fit: Option<String>,
+    ) -> Result<()> {
+        let scene: SceneKind = match scene {
+            Some(scene) => parse_value(scene).wrap_err("invalid scene")?,
+            None => SceneKind::for_path(&path),
+        };
+        let fit: Option<FitMode> = fit
+            .map(|fit| parse_value(fit).wrap_err("invalid fit"))
+            .transpose()?;
+
+        for index in self.select_outputs(output)? {
+            let mut wallpaper = self.outputs[index].latest_wallpaper().clone();
+            wallpaper.path = Some(path.clone());
+            wallpaper.playlist.clear();
+            wallpaper.scene = scene;
+            if let Some(fit) = fit {
+                wallpaper.fit = fit;
+            }
+            self.replace_wallpaper(qh, index, wallpaper)?;
+        }
+
+        Ok(())
+    }
+
+    /// Loads a new wallpaper on an output, keeping the old one when it fails to load. Images
+    /// are decoded in the background, the old wallpaper shows until they are ready.
+    fn replace_wallpaper(
+        &mut self,
+        qh: &QueueHandle<Self>,
+        index: usize,
+        wallpaper: WallpaperConfig,
+    ) -> Result<()> {
+        // Not configured yet, the first configure loads it
+        if self.outputs[index].size == (0, 0) {
+            self.outputs[index].wallpaper = wallpaper;
+            return Ok(());
+        }
+        if SceneType::decodes(&wallpaper) {
+            return self.load_in_background(index, wallpaper);
+        }
+
+        let previous = std::mem::replace(&mut self.outputs[index].wallpaper, wallpaper);
+        self.begin_transition(index);
+        if let Err(err) = self.load_scene(index) {
+            let output = &mut self.outputs[index];
+            output.wallpaper = previous;
+            output.transition = None;
+            return Err(err);
+        }
+
+        self.draw_output(qh, index);
+        Ok(())
+    }
+
+    fn set_paused(
+        &mut self,
+        qh: &QueueHandle<Self>,
+        output: Option<&str>,
+        paused: bool,
+    ) -> Result<()> {
+        for index in self.select_outputs(output)? {
+            let output = &mut self.outputs[index];
+            if output.paused == paused {
+                continue;
+            }
+            output.paused = paused;
+            output.scene.set_paused(paused);
+            if let Some(target) = &mut output.shm_surface {
+                target.scene.set_paused(paused);
+            }
+
+            // Restarts the render loop of animated scenes
+            if !paused && output.size != (0, 0) {
+                self.draw_output(qh, index);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Moves `step` images through a slideshow, or `step` files forward or back in the
+    /// directory of any other wallpaper.
+    fn step_wallpaper(
+        &mut self,
+        qh: &QueueHandle<Self>,
+        output: Option<&str>,
+        step: isize,
+    ) -> Result<()> {
+        for index in self.select_outputs(output)? {
+            if let SceneType::SlideshowBackground(_) = self.outputs[index].scene {
+                self.step_slideshow(qh, index, step)?;
+                continue;
+            }
+
+            let mut wallpaper = self.outputs[index].latest_wallpaper().clone();
+            let Some(current) = &wallpaper.path else {
+                bail!("the output has no wallpaper file to step from");
+            };
+
+            let next = sibling(current, step)?;
+            wallpaper.scene = SceneKind::for_path(&next);
+            wallpaper.path = Some(next);
+            self.replace_wallpaper(qh, index, wallpaper)?;
+        }
+
+        Ok(())
+    }
+
+    /// The image is decoded in the background, drawing the output keeps checking for it.
+    fn step_slideshow(&mut self, qh: &QueueHandle<Self>, index: usize, step: isize) -> Result<()> {
+        let SceneType::SlideshowBackground(scene) = &mut self.outputs[index].scene else {
+            return Ok(());
+        };
+
+        scene.step(step)?;
+        self.draw_output(qh, index);
+        Ok(())
+    }
+
+    fn query(&self) -> Response {
+        let renderer = if self.shm.is_some() { "shm" } else { "gpu" };
+        let outputs = (0..self.outputs.len())
+            .map(|index| {
+                let output = &self.outputs[index];
+                let path = match &output.scene {
+                    SceneType::SlideshowBackground(scene) => Some(scene.current_path().into()),
+                    _ => output.wallpaper.path.clone(),
+                };
+                OutputStatus {
+                    name: self.output_name(index),
+                    scene: value_name(output.wallpaper.scene),
+                    path,
+                    fit: value_name(output.wallpaper.fit),
+                    paused: output.paused,
+                    width: output.size.0,
+                    height: output.size.1,
+                    renderer: renderer.into(),
+                }
+            })
+            .collect();
+
+        Response::State { outputs }
+    }
+}
+
+/// Parses a config value such as a [`SceneKind`] from its name in the config file.
+fn parse_value<T: serde::de::DeserializeOwned>(name: String) -> Result<T> {
+    Ok(serde_json::from_value(serde_json::Value::String(name))?)
+}
+
+/// Name of a config value in the config file.
+fn value_name(value: impl serde::Serialize) -> String {
+    match serde_json::to_value(value) {
+        Ok(serde_json::Value::String(name)) => name,
+        _ => String::new(),
+    }
+}
+
+/// The file `step` places away from `path` in its directory, wrapping around. Only files that
+/// can be shown as a wallpaper are counted.
+fn sibling(path: &Path, step: isize) -> Result<PathBuf> {
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let mut files: Vec<PathBuf> = fs::read_dir(dir)
+        .wrap_err_with(|| format!("failed to list {}", dir.display()))?
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| path.is_file() && is_wallpaper(path))
+        .collect();
+    files.sort();
+
+    if files.is_empty() {
+        bail!("no wallpapers in {}", dir.display());
+    }
+    // The current file may be gone by now, then its sorted position is used
+    let position = match files.binary_search_by(|file| file.as_path().cmp(path)) {
+        Ok(position) => position as isize,
+        Err(position) if step > 0 => position as isize - 1,
+        Err(position) => position as isize,
+    };
+    let next = (position + step).rem_euclid(files.len() as isize);
+
+    Ok(files.swap_remove(next as usize))
+}
+
+fn is_wallpaper(path: &Path) -> bool {
+    match SceneKind::for_path(path) {
+        SceneKind::Image => image::ImageFormat::from_path(path).is_ok(),
+        _ => true,
+    }
+}
@@ -1,22 +1,56 @@
-use color_eyre::config;
+use std::time::{Duration, Instant};
+
+use color_eyre::eyre::{bail, eyre, Result, WrapErr};
 use raw_window_handle::{
     HasRawDisplayHandle, HasRawWindowHandle, RawDisplayHandle, RawWindowHandle,
+    WaylandDisplayHandle, WaylandWindowHandle,
 };
 use smithay_client_toolkit::{
-    compositor::CompositorHandler,
-    delegate_compositor, delegate_layer, delegate_output, delegate_registry, delegate_seat,
-    delegate_xdg_shell,
+    compositor::{CompositorHandler, CompositorState},
+    reexports::calloop::{
+        timer::{TimeoutAction, Timer},
+        channel::Sender,
+        LoopHandle,
+    },
+    delegate_compositor, delegate_layer, delegate_output, delegate_pointer, delegate_registry,
+    delegate_seat, delegate_shm, delegate_xdg_shell,
     output::{OutputHandler, OutputState},
     registry::{ProvidesRegistryState, RegistryState},
     registry_handlers,
-    seat::{SeatHandler, SeatState},
+    seat::{
+        pointer::{PointerEvent, PointerEventKind, PointerHandler},
+        Capability, SeatHandler, SeatState,
+    },
     shell::{
-        wlr_layer::{LayerShellHandler, LayerSurface},
+        wlr_layer::{LayerShell, LayerShellHandler, LayerSurface, LayerSurfaceConfigure},
         WaylandSurface,
     },
+    shm::{Shm, ShmHandler},
+};
+use wayland_client::{
+    globals::GlobalList,
+    protocol::{wl_output::WlOutput, wl_pointer::WlPointer, wl_surface::WlSurface},
+    Connection, Proxy, QueueHandle,
+};
+use wayland_protocols::wp::{
+    fractional_scale::v1::client::wp_fractional_scale_v1::WpFractionalScaleV1,
+    viewporter::client::wp_viewport::WpViewport,
 };
+use wgpu::util::DeviceExt;
 
+use super::animation::{AnimatedScene, FrameSequence};
+use super::cache::ImageCache;
+use super::image_scene::{decode_still, ImageScene};
+use super::loader::{self, Decoded, Loading};
+use super::scale::{Scale, ScaleState};
+use super::shader::ShaderScene;
+use super::shm::{EngineSHM, ShmScene, ShmSurface};
+use super::slideshow::{self, SlideshowScene};
 use super::texture;
+use super::transition::Transition;
+use super::video::VideoScene;
+use crate::cli::{Size, WallpaperArgs};
+use crate::config::{Config, FitMode, Renderer, SceneKind, TransitionKind, WallpaperConfig};
 
 // use crate::texture;
 // mod texture;
@@ -29,6 +63,20 @@ pub trait Engine {
 
 pub struct DisplayHandle(pub RawDisplayHandle, pub RawWindowHandle);
 
+impl DisplayHandle {
+    pub fn wayland(conn: &Connection, surface: &WlSurface) -> Self {
+        let mut handle = WaylandDisplayHandle::empty();
+        handle.display = conn.backend().display_ptr() as *mut _;
+        let display_handle = RawDisplayHandle::Wayland(handle);
+
+        let mut handle = WaylandWindowHandle::empty();
+        handle.surface = surface.id().as_ptr() as *mut _;
+        let surface_handle = RawWindowHandle::Wayland(handle);
+
+        DisplayHandle(display_handle, surface_handle)
+    }
+}
+
 unsafe impl HasRawDisplayHandle for DisplayHandle {
     fn raw_display_handle(&self) -> RawDisplayHandle {
         self.0
@@ -42,14 +90,21 @@ unsafe impl HasRawWindowHandle for DisplayHandle {
 }
 
 #[repr(C)]
-#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
-struct Vertex {
+#[derive(Debug, Default, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Vertex {
     position: [f32; 3],
     tex_coords: [f32; 2],
 }
 
 impl Vertex {
-    fn desc() -> wgpu::VertexBufferLayout<'static> {
+    pub fn new(position: [f32; 2], tex_coords: [f32; 2]) -> Self {
+        Self {
+            position: [position[0], position[1], 0.0],
+            tex_coords,
+        }
+    }
+
+    pub fn desc() -> wgpu::VertexBufferLayout<'static> {
         use std::mem;
         wgpu::VertexBufferLayout {
             array_stride: mem::size_of::<Vertex>() as wgpu::BufferAddress,
@@ -71,17 +126,67 @@ impl Vertex {
 }
 
 pub struct EngineCore {
+    pub instance: wgpu::Instance,
     pub adapter: wgpu::Adapter,
     pub device: wgpu::Device,
     pub queue: wgpu::Queue,
-    pub surface: Option<wgpu::Surface>,
-    pub scene: SceneType,
+    pub format: wgpu::TextureFormat,
+    pub image_bind_group_layout: wgpu::BindGroupLayout,
     pub image_render_pipeline: wgpu::RenderPipeline,
+    /// The old wallpaper of a [`Transition`], drawn with the new one in the image bind group.
+    pub transition_bind_group_layout: wgpu::BindGroupLayout,
+    pub transition_render_pipeline: wgpu::RenderPipeline,
+    pub mipmaps: texture::MipmapGenerator,
+}
+
+/// A wgpu surface together with the configuration it was last configured with.
+pub struct RenderSurface {
+    pub surface: wgpu::Surface,
+    pub config: wgpu::SurfaceConfiguration,
+}
+
+/// Pixels of an image or animated wallpaper, ready to be uploaded.
+#[derive(Clone)]
+pub enum DecodedImage {
+    Still(image::DynamicImage),
+    Animated(FrameSequence),
+}
+
+impl DecodedImage {
+    /// Shrinks pixels larger than textures of `limit` pixels a side, which
+    /// [`texture::Texture::from_image`] would otherwise do on the event loop. Returns the size
+    /// they had when they were shrunk, to place them with, see [`SceneType::place_as`].
+    pub fn shrink_to_limit(&mut self, limit: u32) -> Option<(u32, u32)> {
+        match self {
+            DecodedImage::Still(img) => {
+                let size = (img.width(), img.height());
+                *img = texture::shrink_to_limit(img, limit, Some("Wallpaper"))?;
+                Some(size)
+            }
+            DecodedImage::Animated(sequence) => {
+                let size = sequence.frames.first()?.0.dimensions();
+                if size.0 <= limit && size.1 <= limit {
+                    return None;
+                }
+                for (i, (frame, _)) in sequence.frames.iter_mut().enumerate() {
+                    let img = image::DynamicImage::ImageRgba8(std::mem::take(frame));
+                    let label = format!("Animation frame {i}");
+                    *frame = texture::shrink_to_limit(&img, limit, Some(&label))
+                        .unwrap_or(img)
+                        .into_rgba8();
+                }
+                Some(size)
+            }
+        }
+    }
 }
 
 pub enum SceneType {
-    ImageBackground,
-    GifBackground,
+    ImageBackground(ImageScene),
+    AnimatedBackground(AnimatedScene),
+    VideoBackground(VideoScene),
+    ShaderBackground(ShaderScene),
+    SlideshowBackground(Box<SlideshowScene>),
     Scene2D(Scene2DWrapper),
     Scene3D,
     None,
@@ -93,6 +198,231 @@ impl Default for SceneType {
     }
 }
 
+impl SceneType {
+    /// Builds the scene described by a wallpaper config.
+    pub fn load(core: &EngineCore, wallpaper: &WallpaperConfig) -> Result<Self> {
+        let path = || {
+            wallpaper
+                .path
+                .as_ref()
+                .ok_or_else(|| eyre!("{:?} scene needs a path", wallpaper.scene))
+        };
+
+        match wallpaper.scene {
+            SceneKind::Image | SceneKind::Animated => {
+                Self::from_decoded(core, wallpaper, Self::decode(wallpaper, None)?)
+            }
+            SceneKind::Video => {
+                let scene = VideoScene::new(
+                    core,
+                    path()?,
+                    wallpaper.fit,
+                    wallpaper.background.into(),
+                    wallpaper.fps,
+                )?;
+                Ok(SceneType::VideoBackground(scene))
+            }
+            SceneKind::Shader => {
+                let scene = ShaderScene::new(core, path()?)?;
+                Ok(SceneType::ShaderBackground(scene))
+            }
+            SceneKind::Slideshow => {
+                let scene = SlideshowScene::new(wallpaper)?;
+                Ok(SceneType::SlideshowBackground(Box::new(scene)))
+            }
+            SceneKind::None => Ok(SceneType::None),
+        }
+    }
+
+    /// Whether the scene is an image or animation, which [`SceneType::decode`] can prepare
+    /// away from the GPU.
+    pub fn decodes(wallpaper: &WallpaperConfig) -> bool {
+        matches!(wallpaper.scene, SceneKind::Image | SceneKind::Animated)
+    }
+
+    /// Whether [`SceneType::decode`] prepares the same pixels for both wallpapers, so outputs
+    /// showing them can share them.
+    pub fn decodes_alike(a: &WallpaperConfig, b: &WallpaperConfig) -> bool {
+        a.scene == b.scene
+            && a.path == b.path
+            && a.fit == b.fit
+            && a.downscale == b.downscale
+            && a.cache == b.cache
+    }
+
+    /// Reads and decodes the file of an image or animated wallpaper. Does not touch the GPU, so
+    /// it can run on a worker thread. Still images are shrunk to the size they are drawn at on
+    /// a `target` of that many pixels, if given, and cached on disk for that size.
+    pub fn decode(
+        wallpaper: &WallpaperConfig,
+        target: Option<(u32, u32)>,
+    ) -> Result<DecodedImage> {
+        let path = wallpaper
+            .path
+            .as_ref()
+            .ok_or_else(|| eyre!("{:?} scene needs a path", wallpaper.scene))?;
+        let bytes =
+            std::fs::read(path).wrap_err_with(|| format!("failed to read {}", path.display()))?;
+
+        // APNG and animated WebP share their extension with still images
+        if wallpaper.scene == SceneKind::Image && !FrameSequence::is_animated(&bytes) {
+            let cache = wallpaper.cache.then(ImageCache::open).flatten();
+            let img = decode_still(
+                &bytes,
+                wallpaper.fit,
+                wallpaper.downscale,
+                target,
+                cache.as_ref(),
+            )
+            .wrap_err_with(|| format!("failed to load {}", path.display()))?;
+            return Ok(DecodedImage::Still(img));
+        }
+
+        let frames = FrameSequence::decode(&bytes)
+            .wrap_err_with(|| format!("failed to decode {}", path.display()))?;
+        Ok(DecodedImage::Animated(frames))
+    }
+
+    /// Uploads the result of [`SceneType::decode`].
+    pub fn from_decoded(
+        core: &EngineCore,
+        wallpaper: &WallpaperConfig,
+        decoded: DecodedImage,
+    ) -> Result<Self> {
+        let background = wallpaper.background.into();
+        match decoded {
+            DecodedImage::Still(img) => {
+                let scene =
+                    ImageScene::new(core, &img, wallpaper.fit, wallpaper.downscale, background)?;
+                Ok(SceneType::ImageBackground(scene))
+            }
+            DecodedImage::Animated(frames) => {
+                let scene = AnimatedScene::new(core, frames, wallpaper.fit, background)?;
+                Ok(SceneType::AnimatedBackground(scene))
+            }
+        }
+    }
+
+    /// Places an image or animation as if it had `size` pixels, the size it was decoded at
+    /// before [`DecodedImage::shrink_to_limit`]. Takes effect with the next resize.
+    pub fn place_as(&mut self, size: (u32, u32)) {
+        match self {
+            SceneType::ImageBackground(scene) => scene.image_size = size,
+            SceneType::AnimatedBackground(scene) => scene.image_size = size,
+            _ => {}
+        }
+    }
+
+    /// Adapts the scene to a new target size in pixels.
+    pub fn resize(&mut self, core: &EngineCore, width: u32, height: u32) {
+        match self {
+            SceneType::ImageBackground(scene) => scene.resize(core, width, height),
+            SceneType::AnimatedBackground(scene) => scene.resize(core, width, height),
+            SceneType::VideoBackground(scene) => scene.resize(core, width, height),
+            SceneType::ShaderBackground(scene) => scene.resize(width, height),
+            SceneType::SlideshowBackground(scene) => scene.resize(core, width, height),
+            SceneType::Scene2D(_)
+            | SceneType::Scene3D
+            | SceneType::None => {}
+        }
+    }
+
+    pub fn clear_color(&self) -> wgpu::Color {
+        match self {
+            SceneType::ImageBackground(scene) => scene.background,
+            SceneType::AnimatedBackground(scene) => scene.background,
+            SceneType::VideoBackground(scene) => scene.background,
+            SceneType::SlideshowBackground(scene) => scene.background(),
+            _ => wgpu::Color::BLACK,
+        }
+    }
+
+    pub fn draw<'a>(&'a self, core: &'a EngineCore, pass: &mut wgpu::RenderPass<'a>) {
+        match self {
+            SceneType::ImageBackground(scene) => scene.draw(core, pass),
+            SceneType::Scene2D(scene) => {
+                for image in &scene.images {
+                    image.draw(core, pass);
+                }
+            }
+            SceneType::AnimatedBackground(scene) => scene.draw(core, pass),
+            SceneType::VideoBackground(scene) => scene.draw(core, pass),
+            SceneType::ShaderBackground(scene) => scene.draw(pass),
+            SceneType::SlideshowBackground(scene) => scene.draw(core, pass),
+            SceneType::Scene3D | SceneType::None => {}
+        }
+    }
+
+    /// Whether the scene still changes over time and needs to be redrawn.
+    pub fn is_animated(&self) -> bool {
+        match self {
+            SceneType::AnimatedBackground(scene) => scene.is_playing(),
+            SceneType::VideoBackground(scene) => scene.is_playing(),
+            SceneType::ShaderBackground(scene) => scene.is_playing(),
+            SceneType::SlideshowBackground(scene) => scene.is_playing(),
+            _ => false,
+        }
+    }
+
+    /// Freezes or resumes an animated scene, static scenes ignore this.
+    pub fn set_paused(&mut self, paused: bool) {
+        match self {
+            SceneType::AnimatedBackground(scene) => scene.set_paused(paused),
+            SceneType::VideoBackground(scene) => scene.set_paused(paused),
+            SceneType::ShaderBackground(scene) => scene.set_paused(paused),
+            SceneType::SlideshowBackground(scene) => scene.set_paused(paused),
+            _ => {}
+        }
+    }
+
+    /// Pointer position in pixels, for scenes that react to it.
+    pub fn set_mouse(&mut self, x: f32, y: f32) {
+        if let SceneType::ShaderBackground(scene) = self {
+            scene.set_mouse(x, y);
+        }
+    }
+
+    /// Whether the next update replaces what the scene shows, e.g. with the next image of a
+    /// slideshow.
+    pub fn switches(&mut self) -> bool {
+        match self {
+            SceneType::SlideshowBackground(scene) => scene.switches(),
+            _ => false,
+        }
+    }
+
+    /// Blocks until the images or video frames the scene is waiting for are decoded and shown.
+    /// For rendering a single frame, where nothing else needs the event loop.
+    pub fn wait_for_decoder(&mut self, core: &EngineCore) {
+        match self {
+            SceneType::VideoBackground(scene) => scene.wait_for_first_frame(core),
+            SceneType::SlideshowBackground(scene) => scene.wait_for_decoder(core),
+            _ => {}
+        }
+    }
+
+    /// Earliest time at which the scene changes again, `None` when it changes every frame.
+    pub fn next_update(&self) -> Option<Instant> {
+        match self {
+            SceneType::AnimatedBackground(scene) => scene.next_update(),
+            SceneType::VideoBackground(scene) => scene.next_update(),
+            SceneType::SlideshowBackground(scene) => scene.next_update(),
+            _ => None,
+        }
+    }
+
+    /// Advances the scene to `now`. Returns whether it looks different than before.
+    pub fn update(&mut self, core: &EngineCore, now: Instant) -> bool {
+        match self {
+            SceneType::AnimatedBackground(scene) => scene.update(now),
+            SceneType::VideoBackground(scene) => scene.update(core, now),
+            SceneType::ShaderBackground(scene) => scene.update(core, now),
+            SceneType::SlideshowBackground(scene) => scene.update(core, now),
+            _ => false,
+        }
+    }
+}
+
 pub struct Scene2DWrapper {
     // TODO: camera
     pub images: Vec<SimpleImage>,
@@ -106,9 +436,9 @@ pub struct SimpleImage {
 }
 
 impl SimpleImage {
-    pub fn get_image_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
-        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            entries: &[
+    /// A texture and its sampler, shared with the layouts that extend the image layout.
+    pub fn image_layout_entries() -> [wgpu::BindGroupLayoutEntry; 2] {
+        [
                 wgpu::BindGroupLayoutEntry {
                     binding: 0,
                     visibility: wgpu::ShaderStages::FRAGMENT,
@@ -125,54 +455,129 @@ impl SimpleImage {
                     ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
                     count: None,
                 },
-            ],
+        ]
+    }
+
+    pub fn get_image_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &Self::image_layout_entries(),
             label: Some("Simple image"),
         })
     }
+
+    /// Sampler for drawing an image with the given fit mode.
+    pub fn create_sampler(core: &EngineCore, fit: FitMode) -> wgpu::Sampler {
+        // Tiling repeats the texture through tex coords outside of 0..1
+        let address_mode = match fit {
+            FitMode::Tile => wgpu::AddressMode::Repeat,
+            _ => wgpu::AddressMode::ClampToEdge,
+        };
+
+        core.device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: address_mode,
+            address_mode_v: address_mode,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            // Textures from `Texture::from_image` have mipmaps, the others only one level
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        })
+    }
+
+    pub fn create_bind_group(
+        core: &EngineCore,
+        texture: &texture::Texture,
+        sampler: &wgpu::Sampler,
+    ) -> wgpu::BindGroup {
+        core.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Simple image"),
+            layout: &core.image_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+            ],
+        })
+    }
+
+    pub fn new(core: &EngineCore, texture: texture::Texture, fit: FitMode) -> Self {
+        let sampler = Self::create_sampler(core, fit);
+        let bind_group = Self::create_bind_group(core, &texture, &sampler);
+
+        let vertex_buffer = core
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Simple image quad"),
+                contents: bytemuck::cast_slice(&[Vertex::default(); 6]),
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            });
+
+        Self {
+            texture,
+            bind_group,
+            vertex_buffer,
+        }
+    }
+
+    pub fn draw<'a>(&'a self, core: &'a EngineCore, pass: &mut wgpu::RenderPass<'a>) {
+        pass.set_pipeline(&core.image_render_pipeline);
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        pass.draw(0..6, 0..1);
+    }
 }
 
 impl EngineCore {
-    pub fn init_wgpu(display: Option<DisplayHandle>) -> Self {
+    /// Creates the device. When a display handle is given, the adapter is picked so that it can
+    /// present to that surface.
+    pub fn init_wgpu(display: Option<&DisplayHandle>) -> Result<Self> {
         let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
             backends: wgpu::Backends::all(),
             dx12_shader_compiler: Default::default(),
         });
 
-        let surface_owned = if let Some(handle) = display {
-            Some(unsafe { instance.create_surface(&handle).unwrap() })
-        } else {
-            None
+        let surface = match display {
+            Some(handle) => Some(unsafe { instance.create_surface(handle)? }),
+            None => None,
         };
 
-        let surface = surface_owned.as_ref().unwrap();
-
-        let adapter =
+        let request_adapter = |force_fallback_adapter| {
             pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptionsBase {
-                compatible_surface: Some(surface),
+                compatible_surface: surface.as_ref(),
+                force_fallback_adapter,
                 ..Default::default()
             }))
-            .expect("Failed to get adapter");
+        };
+        // Machines without a GPU may still have a software adapter, e.g. llvmpipe
+        let adapter = request_adapter(false)
+            .or_else(|| request_adapter(true))
+            .ok_or_else(|| eyre!("Failed to get adapter"))?;
 
-        let (device, queue) = pollster::block_on(adapter.request_device(&Default::default(), None))
-            .expect("Failed to get device");
+        let (device, queue) =
+            pollster::block_on(adapter.request_device(&Default::default(), None))?;
 
-        let cap = surface.get_capabilities(&adapter);
-        let surface_config = wgpu::SurfaceConfiguration {
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            format: cap.formats[0],
-            view_formats: vec![cap.formats[0]],
-            alpha_mode: wgpu::CompositeAlphaMode::Auto,
-            width: 500,  //TODO:
-            height: 500, //TODO:
-            present_mode: wgpu::PresentMode::Fifo,
+        let format = match &surface {
+            Some(surface) => {
+                let cap = surface.get_capabilities(&adapter);
+                cap.formats
+                    .iter()
+                    .copied()
+                    .find(|f| f.is_srgb())
+                    .unwrap_or(cap.formats[0])
+            }
+            None => wgpu::TextureFormat::Rgba8UnormSrgb,
         };
 
-        surface.configure(&device, &surface_config);
-
+        let image_bind_group_layout = SimpleImage::get_image_bind_group_layout(&device);
         let render_pipeline_layout =
             device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
                 label: Some("Simple Image Renderer"),
-                bind_group_layouts: &[&SimpleImage::get_image_bind_group_layout(&device)],
+                bind_group_layouts: &[&image_bind_group_layout],
                 push_constant_ranges: &[],
             });
 
@@ -181,107 +586,148 @@ impl EngineCore {
             source: wgpu::ShaderSource::Wgsl(include_str!("image.wgsl").into()),
         });
 
-        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Simple Image Render Pipeline"),
-            layout: Some(&render_pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: "vs_main",
-                buffers: &[Vertex::desc()],
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: "fs_main",
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: surface_config.format,
-                    blend: Some(wgpu::BlendState {
-                        color: wgpu::BlendComponent::REPLACE,
-                        alpha: wgpu::BlendComponent::REPLACE,
-                    }),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: Some(wgpu::Face::Back),
-                unclipped_depth: false,
-                polygon_mode: wgpu::PolygonMode::Fill,
-                conservative: false,
-            },
-            depth_stencil: None,
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
-            },
-            multiview: None,
+        let render_pipeline = quad_pipeline(
+            &device,
+            format,
+            "Simple Image Render Pipeline",
+            &render_pipeline_layout,
+            &shader,
+        );
+        let mipmaps = texture::MipmapGenerator::new(&device, &render_pipeline_layout, &shader);
+
+        let transition_bind_group_layout = Transition::bind_group_layout(&device);
+        let transition_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Transition Renderer"),
+                bind_group_layouts: &[&image_bind_group_layout, &transition_bind_group_layout],
+                push_constant_ranges: &[],
+            });
+        let transition_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Transition shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("transition.wgsl").into()),
         });
+        let transition_render_pipeline = quad_pipeline(
+            &device,
+            format,
+            "Transition Render Pipeline",
+            &transition_pipeline_layout,
+            &transition_shader,
+        );
 
-        Self {
+        Ok(Self {
+            instance,
             adapter,
             device,
             queue,
-            surface: surface_owned,
-            scene: Default::default(),
+            format,
+            image_bind_group_layout,
             image_render_pipeline: render_pipeline,
-        }
+            transition_bind_group_layout,
+            transition_render_pipeline,
+            mipmaps,
+        })
     }
 
-    pub fn configure(&mut self) {
-        // let width = 500;
-        // let height = 500;
+    /// Creates and configures a surface for the given window or layer.
+    pub fn create_surface(
+        &self,
+        display: &DisplayHandle,
+        width: u32,
+        height: u32,
+    ) -> Result<RenderSurface> {
+        let surface = unsafe { self.instance.create_surface(display)? };
 
-        // let adapter = &self.adapter;
-        // let surface = self.surface.as_ref().unwrap();
-        // let device = &self.device;
-        // let _queue = &self.queue;
+        let cap = surface.get_capabilities(&self.adapter);
+        if !cap.formats.contains(&self.format) {
+            bail!("surface does not support the {:?} format", self.format);
+        }
 
-        // let cap = surface.get_capabilities(&adapter);
-        // let surface_config = wgpu::SurfaceConfiguration {
-        //     usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-        //     format: cap.formats[0],
-        //     view_formats: vec![cap.formats[0]],
-        //     alpha_mode: wgpu::CompositeAlphaMode::Auto,
-        //     width: width,
-        //     height: height,
-        //     present_mode: wgpu::PresentMode::Fifo,
-        // };
+        let config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format: self.format,
+            view_formats: vec![self.format],
+            alpha_mode: wgpu::CompositeAlphaMode::Auto,
+            width,
+            height,
+            present_mode: wgpu::PresentMode::Fifo,
+        };
+        surface.configure(&self.device, &config);
 
-        // surface.configure(&device, &surface_config);
+        Ok(RenderSurface { surface, config })
     }
 
-    pub fn render(&self) {
-        let surface_texture = self
-            .surface
-            .as_ref()
-            .unwrap()
-            .get_current_texture()
-            .expect("faield to acquire next swapchain texture");
+    /// Reconfigures the surface for a new size, e.g. after the compositor resized the layer.
+    pub fn configure(&self, target: &mut RenderSurface, width: u32, height: u32) {
+        // wgpu refuses zero sized surfaces
+        target.config.width = width.max(1);
+        target.config.height = height.max(1);
+
+        target.surface.configure(&self.device, &target.config);
+    }
+
+    /// Presents a frame of `scene`, blended with the previous wallpaper while a transition runs.
+    /// The frame is skipped when the surface has no texture to draw into.
+    pub fn render(
+        &self,
+        target: &RenderSurface,
+        scene: &SceneType,
+        transition: Option<&Transition>,
+    ) {
+        let surface_texture = match target.surface.get_current_texture() {
+            Ok(texture) => texture,
+            // Reconfiguring with the current size makes the surface usable again
+            Err(wgpu::SurfaceError::Outdated | wgpu::SurfaceError::Lost) => {
+                target.surface.configure(&self.device, &target.config);
+                match target.surface.get_current_texture() {
+                    Ok(texture) => texture,
+                    Err(err) => {
+                        log::warn!("Skipping a frame, the surface is still unusable: {err}");
+                        return;
+                    }
+                }
+            }
+            Err(wgpu::SurfaceError::Timeout) => {
+                log::debug!("Skipping a frame, the surface timed out");
+                return;
+            }
+            Err(err) => {
+                log::error!("Failed to acquire the next surface texture: {err}");
+                return;
+            }
+        };
 
         let texture_view = surface_texture
             .texture
             .create_view(&wgpu::TextureViewDescriptor::default());
 
+        match transition {
+            Some(transition) => transition.draw(self, &texture_view, scene),
+            None => self.draw_scene(&texture_view, scene),
+        }
+        surface_texture.present();
+    }
+
+    /// Clears `view` to the background of the scene and draws the scene on top.
+    pub fn draw_scene(&self, texture_view: &wgpu::TextureView, scene: &SceneType) {
         let mut ecnoder = self.device.create_command_encoder(&Default::default());
         {
-            let _renderpass = ecnoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            let mut renderpass = ecnoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: None,
                 color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &texture_view,
+                    view: texture_view,
                     resolve_target: None,
                     ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color::GREEN),
+                        load: wgpu::LoadOp::Clear(scene.clear_color()),
                         store: true,
                     },
                 })],
                 depth_stencil_attachment: None,
             });
+
+            scene.draw(self, &mut renderpass);
         }
 
         self.queue.submit(Some(ecnoder.finish()));
-        surface_texture.present();
     }
 
     pub fn update(&self) {
@@ -289,6 +735,53 @@ impl EngineCore {
     }
 }
 
+/// Pipeline drawing [`Vertex`] quads with the `vs_main` and `fs_main` entry points of `shader`.
+pub fn quad_pipeline(
+    device: &wgpu::Device,
+    format: wgpu::TextureFormat,
+    label: &str,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(label),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            buffers: &[Vertex::desc()],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: "fs_main",
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: Some(wgpu::BlendState {
+                    color: wgpu::BlendComponent::REPLACE,
+                    alpha: wgpu::BlendComponent::REPLACE,
+                }),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: Some(wgpu::Face::Back),
+            unclipped_depth: false,
+            polygon_mode: wgpu::PolygonMode::Fill,
+            conservative: false,
+        },
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState {
+            count: 1,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        multiview: None,
+    })
+}
+
 
 const VERTICES: &[Vertex] = &[
     Vertex { position: [-0.0868241, 0.49240386, 0.0], tex_coords: [0.4131759, 0.99240386], }, // A
@@ -303,36 +796,519 @@ pub struct EngineShell {
     pub registry_state: RegistryState,
     pub output_state: OutputState,
     pub seat_state: SeatState,
-    pub layer: LayerSurface,
-    pub core: EngineCore,
+    /// Feeds the pointer position to shader wallpapers.
+    pub pointer: Option<WlPointer>,
+    pub compositor_state: CompositorState,
+    pub layer_shell: LayerShell,
+    pub shm_state: Shm,
+    pub scale_state: ScaleState,
+    pub config: Config,
+    pub overrides: WallpaperArgs,
+    /// Event loop the shell runs in, used for redraw timers.
+    pub loop_handle: LoopHandle<'static, EngineShell>,
+    /// Sends wallpapers decoded on worker threads back to the event loop.
+    pub decoder: Sender<Decoded>,
+    /// Id of the next wallpaper loaded in the background.
+    pub next_load: u64,
+    /// Handed to slideshows, whose decoder threads wake the event loop with it.
+    pub slideshow_waker: Sender<()>,
+    /// Created together with the first output surface, so the adapter can present to it.
+    pub core: Option<EngineCore>,
+    /// Used instead of `core` when there is no adapter, or when configured.
+    pub shm: Option<EngineSHM>,
+    pub outputs: Vec<OutputSurface>,
     pub exit: bool,
 }
 
-#[allow(dead_code)]
-struct EngineSHM {
-    core: EngineCore,
+/// Wallpaper surface of a single output.
+pub struct OutputSurface {
+    pub output: WlOutput,
+    pub wallpaper: WallpaperConfig,
+    /// Size in surface-local coordinates, as configured by the compositor.
+    pub logical_size: (u32, u32),
+    /// Size of the buffers in pixels.
+    pub size: (u32, u32),
+    pub scale: Scale,
+    /// Only present when the compositor supports wp_viewporter.
+    pub viewport: Option<WpViewport>,
+    /// Only present when the compositor supports fractional scaling, and only used together
+    /// with the viewport.
+    pub fractional_scale: Option<WpFractionalScaleV1>,
+    pub scene: SceneType,
+    /// Blends the previous wallpaper into `scene` after it was replaced.
+    pub transition: Option<Transition>,
+    /// Wallpaper being decoded in the background to replace `scene`.
+    pub loading: Option<Loading>,
+    /// Size the image of `scene` was shrunk for when it was decoded, it is decoded again for
+    /// larger sizes.
+    pub decoded_for: Option<(u32, u32)>,
+    /// A frame callback was requested and has not been received yet.
+    pub frame_pending: bool,
+    /// A timer will draw the next frame.
+    pub redraw_timer: bool,
+    pub last_render: Option<Instant>,
+    /// Paused over the control socket, applies to wallpapers set later on too.
+    pub paused: bool,
+    /// Created on the first configure, once the compositor told us the size.
+    // Declared before `layer` so the wgpu surface is dropped before the wl_surface it draws to.
+    pub surface: Option<RenderSurface>,
+    /// Replaces `scene` and `surface` when rendering in software.
+    pub shm_surface: Option<ShmSurface>,
+    pub layer: LayerSurface,
+}
+
+impl OutputSurface {
+    /// The wallpaper that is loading, or else the one showing.
+    pub fn latest_wallpaper(&self) -> &WallpaperConfig {
+        self.loading
+            .as_ref()
+            .map_or(&self.wallpaper, |loading| &loading.wallpaper)
+    }
+
+    /// Whether the wallpaper still changes over time, with whichever renderer draws it.
+    fn is_animated(&self) -> bool {
+        match &self.shm_surface {
+            Some(target) => target.scene.is_animated(),
+            None => self.transition.is_some() || self.scene.is_animated(),
+        }
+    }
+
+    fn next_update(&self) -> Option<Instant> {
+        if self.transition.is_some() {
+            return None;
+        }
+        match &self.shm_surface {
+            Some(target) => target.scene.next_update(),
+            None => self.scene.next_update(),
+        }
+    }
+}
+
+impl Drop for OutputSurface {
+    fn drop(&mut self) {
+        if let Some(fractional_scale) = self.fractional_scale.take() {
+            fractional_scale.destroy();
+        }
+        if let Some(viewport) = self.viewport.take() {
+            viewport.destroy();
+        }
+    }
+}
+
+impl EngineShell {
+    pub fn new(
+        globals: &GlobalList,
+        qh: &QueueHandle<Self>,
+        loop_handle: LoopHandle<'static, Self>,
+        config: Config,
+        overrides: WallpaperArgs,
+    ) -> Result<Self> {
+        Ok(Self {
+            registry_state: RegistryState::new(globals),
+            output_state: OutputState::new(globals, qh),
+            seat_state: SeatState::new(globals, qh),
+            pointer: None,
+            compositor_state: CompositorState::bind(globals, qh)
+                .wrap_err("wl_compositor not available")?,
+            layer_shell: LayerShell::bind(globals, qh).wrap_err("wlr-layer-shell not available")?,
+            shm_state: Shm::bind(globals, qh).wrap_err("wl_shm not available")?,
+            scale_state: ScaleState::bind(globals, qh),
+            config,
+            overrides,
+            decoder: loader::bind(&loop_handle, qh)?,
+            next_load: 0,
+            slideshow_waker: slideshow::bind(&loop_handle, qh)?,
+            loop_handle,
+            core: None,
+            shm: None,
+            outputs: Vec::new(),
+            exit: false,
+        })
+    }
+
+    fn add_output(&mut self, qh: &QueueHandle<Self>, output: WlOutput) -> Result<()> {
+        let info = self.output_state.info(&output);
+        let name = info.as_ref().and_then(|info| info.name.clone());
+        let scale_factor = info.map_or(1, |info| info.scale_factor);
+        if let Some(only) = &self.overrides.output {
+            if name.as_ref() != Some(only) {
+                return Ok(());
+            }
+        }
+
+        let mut wallpaper = self.config.wallpaper_for(name.as_deref()).clone();
+        wallpaper.apply_args(&self.overrides);
+        // The size may come from the command line, after the config was checked
+        wallpaper.check_size()?;
+
+        let surface = self.compositor_state.create_surface(qh);
+        let layer = self.layer_shell.create_layer_surface(
+            qh,
+            surface,
+            wallpaper.layer.into(),
+            Some("Aphrodite"),
+            Some(&output),
+        );
+        layer.set_anchor(wallpaper.anchor());
+        // Zero lets the compositor size the surface to the anchored edges
+        let size = wallpaper.size.unwrap_or(Size::new(0, 0));
+        layer.set_size(size.width, size.height);
+        layer.set_exclusive_zone(-1);
+
+        let viewport = self.scale_state.viewport(qh, layer.wl_surface());
+        let fractional_scale = viewport
+            .as_ref()
+            .and_then(|_| self.scale_state.fractional_scale(qh, layer.wl_surface()));
+        layer.commit();
+
+        log::info!(
+            "Added wallpaper surface for output {}",
+            name.as_deref().unwrap_or("<unnamed>")
+        );
+        self.outputs.push(OutputSurface {
+            output,
+            wallpaper,
+            logical_size: (0, 0),
+            size: (0, 0),
+            // Until the compositor tells us the preferred scale, guess it from the output
+            scale: Scale::from_integer(scale_factor),
+            viewport,
+            fractional_scale,
+            scene: SceneType::None,
+            transition: None,
+            loading: None,
+            decoded_for: None,
+            frame_pending: false,
+            redraw_timer: false,
+            last_render: None,
+            paused: false,
+            surface: None,
+            shm_surface: None,
+            layer,
+        });
+
+        Ok(())
+    }
+
+    fn configure_output(
+        &mut self,
+        conn: &Connection,
+        qh: &QueueHandle<Self>,
+        layer: &LayerSurface,
+        configure: LayerSurfaceConfigure,
+    ) -> Result<()> {
+        let Some(index) = self.outputs.iter().position(|o| &o.layer == layer) else {
+            return Ok(());
+        };
+        let output = &mut self.outputs[index];
+
+        let (mut width, mut height) = configure.new_size;
+        if width == 0 || height == 0 {
+            // The compositor left the size to us, which only happens when it was set explicitly
+            let size = output.wallpaper.size.unwrap_or(Size::new(1, 1));
+            (width, height) = (size.width, size.height);
+        }
+        output.logical_size = (width, height);
+
+        self.resize_output(conn, qh, index)
+    }
+
+    /// Called when the preferred scale of a wallpaper surface changes.
+    pub fn set_scale(
+        &mut self,
+        conn: &Connection,
+        qh: &QueueHandle<Self>,
+        surface: &WlSurface,
+        scale: Scale,
+    ) {
+        let Some(index) = self.output_index(surface) else {
+            return;
+        };
+        let output = &mut self.outputs[index];
+        if output.scale == scale {
+            return;
+        }
+        output.scale = scale;
+
+        // Not configured yet, the first configure will pick the scale up
+        if output.logical_size == (0, 0) {
+            return;
+        }
+
+        if let Err(err) = self.resize_output(conn, qh, index) {
+            log::error!("Failed to rescale the wallpaper surface: {err:?}");
+        }
+    }
+
+    /// Sizes the buffer of an output surface to its logical size times its scale and redraws it.
+    fn resize_output(
+        &mut self,
+        conn: &Connection,
+        qh: &QueueHandle<Self>,
+        index: usize,
+    ) -> Result<()> {
+        let output = &mut self.outputs[index];
+        let (logical_width, logical_height) = output.logical_size;
+
+        let wl_surface = output.layer.wl_surface();
+        let (width, height) = match (&output.viewport, &output.fractional_scale) {
+            (Some(viewport), Some(_)) => {
+                viewport.set_destination(logical_width as i32, logical_height as i32);
+                (
+                    output.scale.to_physical(logical_width),
+                    output.scale.to_physical(logical_height),
+                )
+            }
+            _ => {
+                let factor = output.scale.integer();
+                wl_surface.set_buffer_scale(factor);
+                (
+                    logical_width * factor as u32,
+                    logical_height * factor as u32,
+                )
+            }
+        };
+
+        if self.core.is_none() && self.shm.is_none() {
+            let wl_surface = wl_surface.clone();
+            self.init_renderer(conn, &wl_surface)?;
+        }
+        let output = &mut self.outputs[index];
+        output.size = (width, height);
+
+        if let Some(target) = &mut output.shm_surface {
+            target.scene.resize(width, height);
+            self.decode_for_new_size(index);
+        } else if let (Some(core), Some(surface)) = (&self.core, &mut output.surface) {
+            core.configure(surface, width, height);
+            output.scene.resize(core, width, height);
+            // Its frames have the old size
+            output.transition = None;
+            self.decode_for_new_size(index);
+        } else {
+            // First configure
+            if let Some(core) = &self.core {
+                let handle = DisplayHandle::wayland(conn, output.layer.wl_surface());
+                output.surface = Some(core.create_surface(&handle, width, height)?);
+            } else if self.shm.is_some() {
+                // Shows the background until the image is decoded, or when it fails to load
+                let mut scene = ShmScene::empty(output.wallpaper.background);
+                scene.resize(width, height);
+                output.shm_surface = Some(ShmSurface::new(scene));
+            }
+            let loaded = if SceneType::decodes(&output.wallpaper) {
+                let wallpaper = output.wallpaper.clone();
+                self.load_in_background(index, wallpaper)
+            } else {
+                self.load_scene(index)
+            };
+            if let Err(err) = loaded {
+                log::error!("Failed to load the wallpaper: {err:?}");
+            }
+        }
+
+        self.draw_output(qh, index);
+
+        Ok(())
+    }
+
+    /// Loads the wallpaper of an output for the active renderer, sized to the output. The
+    /// previous scene stays when loading fails.
+    pub fn load_scene(&mut self, index: usize) -> Result<()> {
+        let output = &mut self.outputs[index];
+        let (width, height) = output.size;
+        // Replaces whatever is still being decoded
+        output.loading = None;
+        output.decoded_for = None;
+
+        if self.shm.is_some() {
+            let mut scene = ShmScene::load(&output.wallpaper)?;
+            scene.resize(width, height);
+            scene.set_paused(output.paused);
+            output.shm_surface = Some(ShmSurface::new(scene));
+        } else if let Some(core) = &self.core {
+            let mut scene = SceneType::load(core, &output.wallpaper)?;
+            if let SceneType::SlideshowBackground(slideshow) = &mut scene {
+                slideshow.set_waker(self.slideshow_waker.clone());
+            }
+            scene.resize(core, width, height);
+            scene.set_paused(output.paused);
+            output.scene = scene;
+        }
+
+        Ok(())
+    }
+
+    /// Creates the wgpu device, or the shared memory pool when configured or when wgpu fails.
+    fn init_renderer(&mut self, conn: &Connection, wl_surface: &WlSurface) -> Result<()> {
+        let handle = DisplayHandle::wayland(conn, wl_surface);
+        match self.config.renderer {
+            Renderer::Gpu => {
+                self.core = Some(EngineCore::init_wgpu(Some(&handle))?);
+                return Ok(());
+            }
+            Renderer::Auto => match EngineCore::init_wgpu(Some(&handle)) {
+                Ok(core) => {
+                    self.core = Some(core);
+                    return Ok(());
+                }
+                Err(err) => log::warn!("wgpu is not available, rendering in software: {err}"),
+            },
+            Renderer::Shm => {}
+        }
+
+        self.shm = Some(EngineSHM::new(&self.shm_state)?);
+        Ok(())
+    }
+
+    pub fn output_index(&self, surface: &WlSurface) -> Option<usize> {
+        self.outputs
+            .iter()
+            .position(|o| o.layer.wl_surface() == surface)
+    }
+
+    /// Renders an output. Animated scenes also ask for a frame callback, which drives the
+    /// render loop, static ones stay idle until something changes.
+    pub fn draw_output(&mut self, qh: &QueueHandle<Self>, index: usize) {
+        let now = Instant::now();
+        if self.outputs[index].scene.switches() {
+            self.begin_transition(index);
+        }
+
+        let output = &mut self.outputs[index];
+        if let (Some(core), Some(_)) = (&self.core, &output.surface) {
+            output.scene.update(core, now);
+            if let Some(transition) = &mut output.transition {
+                transition.update(core, now);
+            }
+        } else if let Some(target) = &mut output.shm_surface {
+            target.scene.update(now);
+        } else {
+            return;
+        }
+
+        // Presenting commits the surface, so the callback has to be requested before
+        let wl_surface = output.layer.wl_surface();
+        if output.is_animated() && !output.frame_pending {
+            wl_surface.frame(qh, wl_surface.clone());
+            output.frame_pending = true;
+        }
+
+        if let (Some(core), Some(surface)) = (&self.core, &output.surface) {
+            core.render(surface, &output.scene, output.transition.as_ref());
+            if output.transition.as_ref().is_some_and(Transition::is_done) {
+                output.transition = None;
+            }
+        }
+        if let (Some(shm), Some(target)) = (&mut self.shm, &mut output.shm_surface) {
+            let viewport = output.viewport.as_ref();
+            let fractional = output.fractional_scale.is_some();
+            if let Err(err) = shm.present(
+                target,
+                wl_surface,
+                viewport,
+                fractional,
+                output.scale,
+                output.logical_size,
+            ) {
+                log::error!("Failed to draw the wallpaper: {err:?}");
+                return;
+            }
+        }
+        wl_surface.commit();
+        output.last_render = Some(now);
+    }
+
+    /// Keeps the current frame of an output, to blend it into the wallpaper that is about to
+    /// replace it. Does nothing when no transition is configured.
+    pub fn begin_transition(&mut self, index: usize) {
+        let output = &mut self.outputs[index];
+        let (Some(core), Some(_)) = (&self.core, &output.surface) else {
+            return;
+        };
+        if output.wallpaper.transition == TransitionKind::None || output.size == (0, 0) {
+            return;
+        }
+
+        output.transition = Some(Transition::new(
+            core,
+            &output.scene,
+            output.transition.as_ref(),
+            output.size,
+            &output.wallpaper,
+        ));
+    }
+
+    /// Draws the next frame of an animated scene once it is due, either right away or from a
+    /// timer. Waiting for the scene keeps e.g. a 10 fps GIF from being presented at 60 fps.
+    fn schedule_redraw(&mut self, qh: &QueueHandle<Self>, index: usize) {
+        let output = &mut self.outputs[index];
+        if !output.is_animated() || output.redraw_timer {
+            return;
+        }
+
+        let now = Instant::now();
+        let mut due = output.next_update().unwrap_or(now);
+        let fps = output.wallpaper.fps.filter(|&fps| fps > 0);
+        if let (Some(last), Some(fps)) = (output.last_render, fps) {
+            due = due.max(last + Duration::from_secs_f64(1.0 / fps as f64));
+        }
+
+        if due <= now {
+            self.draw_output(qh, index);
+            return;
+        }
+
+        let surface = output.layer.wl_surface().clone();
+        let qh = qh.clone();
+        let timer = self
+            .loop_handle
+            .insert_source(Timer::from_deadline(due), move |_, _, shell| {
+                // The output may be gone by now
+                if let Some(index) = shell.output_index(&surface) {
+                    shell.outputs[index].redraw_timer = false;
+                    shell.draw_output(&qh, index);
+                }
+                TimeoutAction::Drop
+            });
+        match timer {
+            Ok(_) => output.redraw_timer = true,
+            Err(err) => log::error!("Failed to schedule a redraw: {}", err.error),
+        }
+    }
 }
 
 impl CompositorHandler for EngineShell {
     fn scale_factor_changed(
         &mut self,
-        _conn: &wayland_client::Connection,
-        _qh: &wayland_client::QueueHandle<Self>,
-        _surface: &wayland_client::protocol::wl_surface::WlSurface,
-        _new_factor: i32,
+        conn: &wayland_client::Connection,
+        qh: &wayland_client::QueueHandle<Self>,
+        surface: &wayland_client::protocol::wl_surface::WlSurface,
+        new_factor: i32,
     ) {
-        // todo!()
+        // With fractional scaling the preferred scale event is authoritative
+        let fractional = self
+            .outputs
+            .iter()
+            .any(|o| o.layer.wl_surface() == surface && o.fractional_scale.is_some());
+        if !fractional {
+            self.set_scale(conn, qh, surface, Scale::from_integer(new_factor));
+        }
     }
 
     fn frame(
         &mut self,
         _conn: &wayland_client::Connection,
-        _qh: &wayland_client::QueueHandle<Self>,
-        _surface: &wayland_client::protocol::wl_surface::WlSurface,
+        qh: &wayland_client::QueueHandle<Self>,
+        surface: &wayland_client::protocol::wl_surface::WlSurface,
         _time: u32,
     ) {
-        println!("Compositor frame");
-        // todo!()
+        let Some(index) = self.output_index(surface) else {
+            return;
+        };
+
+        self.outputs[index].frame_pending = false;
+        self.schedule_redraw(qh, index);
     }
 }
 
@@ -344,9 +1320,12 @@ impl OutputHandler for EngineShell {
     fn new_output(
         &mut self,
         _conn: &wayland_client::Connection,
-        _qh: &wayland_client::QueueHandle<Self>,
-        _output: wayland_client::protocol::wl_output::WlOutput,
+        qh: &wayland_client::QueueHandle<Self>,
+        output: wayland_client::protocol::wl_output::WlOutput,
     ) {
+        if let Err(err) = self.add_output(qh, output) {
+            log::error!("Failed to create a wallpaper surface: {err:?}");
+        }
     }
 
     fn update_output(
@@ -361,8 +1340,9 @@ impl OutputHandler for EngineShell {
         &mut self,
         _conn: &wayland_client::Connection,
         _qh: &wayland_client::QueueHandle<Self>,
-        _output: wayland_client::protocol::wl_output::WlOutput,
+        output: wayland_client::protocol::wl_output::WlOutput,
     ) {
+        self.outputs.retain(|o| o.output != output);
     }
 }
 
@@ -382,10 +1362,16 @@ impl SeatHandler for EngineShell {
     fn new_capability(
         &mut self,
         _conn: &wayland_client::Connection,
-        _qh: &wayland_client::QueueHandle<Self>,
-        _seat: wayland_client::protocol::wl_seat::WlSeat,
-        _capability: smithay_client_toolkit::seat::Capability,
+        qh: &wayland_client::QueueHandle<Self>,
+        seat: wayland_client::protocol::wl_seat::WlSeat,
+        capability: smithay_client_toolkit::seat::Capability,
     ) {
+        if capability == Capability::Pointer && self.pointer.is_none() {
+            match self.seat_state.get_pointer(qh, &seat) {
+                Ok(pointer) => self.pointer = Some(pointer),
+                Err(err) => log::warn!("Failed to get the pointer: {err}"),
+            }
+        }
     }
 
     fn remove_capability(
@@ -393,8 +1379,13 @@ impl SeatHandler for EngineShell {
         _conn: &wayland_client::Connection,
         _qh: &wayland_client::QueueHandle<Self>,
         _seat: wayland_client::protocol::wl_seat::WlSeat,
-        _capability: smithay_client_toolkit::seat::Capability,
+        capability: smithay_client_toolkit::seat::Capability,
     ) {
+        if capability == Capability::Pointer {
+            if let Some(pointer) = self.pointer.take() {
+                pointer.release();
+            }
+        }
     }
 
     fn remove_seat(
@@ -406,33 +1397,65 @@ impl SeatHandler for EngineShell {
     }
 }
 
+impl PointerHandler for EngineShell {
+    fn pointer_frame(
+        &mut self,
+        _conn: &wayland_client::Connection,
+        _qh: &wayland_client::QueueHandle<Self>,
+        _pointer: &WlPointer,
+        events: &[PointerEvent],
+    ) {
+        for event in events {
+            if !matches!(
+                event.kind,
+                PointerEventKind::Enter { .. } | PointerEventKind::Motion { .. }
+            ) {
+                continue;
+            }
+
+            if let Some(output) = self
+                .outputs
+                .iter_mut()
+                .find(|o| o.layer.wl_surface() == &event.surface)
+            {
+                // Positions are surface-local, shaders work in buffer pixels
+                let scale = output.scale.0 as f64 / 120.0;
+                let (x, y) = event.position;
+                output.scene.set_mouse((x * scale) as f32, (y * scale) as f32);
+            }
+        }
+    }
+}
+
 impl LayerShellHandler for EngineShell {
     fn closed(
         &mut self,
         _conn: &wayland_client::Connection,
         _qh: &wayland_client::QueueHandle<Self>,
-        _layer: &LayerSurface,
+        layer: &LayerSurface,
     ) {
-        // todo!()
+        self.outputs.retain(|o| &o.layer != layer);
     }
 
     fn configure(
         &mut self,
-        _conn: &wayland_client::Connection,
+        conn: &wayland_client::Connection,
         qh: &wayland_client::QueueHandle<Self>,
-        _layer: &LayerSurface,
-        _configure: smithay_client_toolkit::shell::wlr_layer::LayerSurfaceConfigure,
+        layer: &LayerSurface,
+        configure: LayerSurfaceConfigure,
         _serial: u32,
     ) {
-        println!("Configure from shell");
+        log::debug!("Configure from shell {:?}", configure.new_size);
 
-        self.core.configure();
-        self.core.render();
-        self.layer.wl_surface().commit();
+        if let Err(err) = self.configure_output(conn, qh, layer, configure) {
+            log::error!("Failed to configure the wallpaper surface: {err:?}");
+        }
+    }
+}
 
-        self.layer
-            .wl_surface()
-            .frame(qh, self.layer.wl_surface().clone());
+impl ShmHandler for EngineShell {
+    fn shm_state(&mut self) -> &mut Shm {
+        &mut self.shm_state
     }
 }
 
@@ -447,6 +1470,8 @@ impl ProvidesRegistryState for EngineShell {
 delegate_compositor!(EngineShell);
 delegate_output!(EngineShell);
 delegate_seat!(EngineShell);
+delegate_pointer!(EngineShell);
+delegate_shm!(EngineShell);
 
 delegate_xdg_shell!(EngineShell);
 delegate_layer!(EngineShell);
@@ -0,0 +1,612 @@
+//! Golden-image tests: every scene is rendered offscreen at fixed points in time and compared
+//! to a reference PNG in `tests/golden`.
+//!
+//! A missing reference is written from the current output and the test fails, so new
+//! references get looked at before they are checked in. Run with `APHRODITE_BLESS=1` to
+//! overwrite all references after an intended change. Failing renders are saved next to the
+//! reference as `<name>.actual.png`, along with a `<name>.diff.png` highlighting mismatches.
+//!
+//! Machines without any wgpu adapter skip the GPU tests, the software renderer is always checked.
+
+use std::{
+    env, fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    time::{Duration, Instant},
+};
+
+use image::{codecs::gif::GifEncoder, Delay, Frame, Rgba, RgbaImage};
+
+use super::cache::ImageCache;
+use super::engine::{DecodedImage, EngineCore, SceneType};
+use super::headless::OffscreenTarget;
+use super::image_scene::{decode_still, ImageScene};
+use super::shm::ShmScene;
+use super::transition::Transition;
+use crate::config::{
+    Color, Downscale, Easing, FitMode, SceneKind, TransitionKind, WallpaperConfig,
+};
+
+const WIDTH: u32 = 64;
+const HEIGHT: u32 = 48;
+
+/// Largest difference of a channel that still counts as a match, to absorb rounding
+/// differences between adapters.
+const CHANNEL_TOLERANCE: u8 = 3;
+/// Share of mismatched pixels that is tolerated, e.g. for filtering differences at edges.
+const MISMATCH_TOLERANCE: f64 = 0.005;
+
+/// Hands out instants at fixed offsets from a single start, instead of reading the wall clock.
+struct StepClock {
+    start: Instant,
+}
+
+impl StepClock {
+    fn new() -> Self {
+        Self {
+            start: Instant::now(),
+        }
+    }
+
+    fn at(&self, millis: u64) -> Instant {
+        self.start + Duration::from_millis(millis)
+    }
+}
+
+struct Harness {
+    core: EngineCore,
+    target: OffscreenTarget,
+    clock: StepClock,
+}
+
+impl Harness {
+    /// `None` when there is no adapter to render with.
+    fn new() -> Option<Self> {
+        let core = match EngineCore::init_wgpu(None) {
+            Ok(core) => core,
+            Err(err) => {
+                eprintln!("Skipping golden-image test, no adapter: {err}");
+                return None;
+            }
+        };
+        let target = OffscreenTarget::new(&core, WIDTH, HEIGHT);
+
+        Some(Self {
+            core,
+            target,
+            clock: StepClock::new(),
+        })
+    }
+
+    fn load(&self, wallpaper: &WallpaperConfig) -> SceneType {
+        let mut scene = SceneType::load(&self.core, wallpaper).expect("failed to load the scene");
+        scene.resize(&self.core, WIDTH, HEIGHT);
+        scene.wait_for_decoder(&self.core);
+        scene
+    }
+
+    /// Advances `scene` to `millis` after the start of the clock.
+    fn update(&self, scene: &mut SceneType, millis: u64) {
+        scene.update(&self.core, self.clock.at(millis));
+    }
+
+    fn render(&self, scene: &SceneType) -> RgbaImage {
+        self.core.render_offscreen(&self.target, scene);
+        self.target
+            .read_pixels(&self.core)
+            .expect("failed to read back the render")
+    }
+
+    fn check(&self, scene: &SceneType, name: &str) {
+        compare(name, &self.render(scene));
+    }
+}
+
+fn golden_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden")
+}
+
+fn compare(name: &str, actual: &RgbaImage) {
+    let dir = golden_dir();
+    let reference_path = dir.join(format!("{name}.png"));
+    let actual_path = dir.join(format!("{name}.actual.png"));
+    let diff_path = dir.join(format!("{name}.diff.png"));
+    let _ = fs::remove_file(&actual_path);
+    let _ = fs::remove_file(&diff_path);
+
+    let bless = env::var_os("APHRODITE_BLESS").is_some();
+    if bless || !reference_path.exists() {
+        actual.save(&reference_path).unwrap();
+        assert!(
+            bless,
+            "wrote the missing reference {}, check it and run again",
+            reference_path.display()
+        );
+        return;
+    }
+
+    let reference = image::open(&reference_path)
+        .unwrap_or_else(|err| panic!("failed to open {}: {err}", reference_path.display()))
+        .into_rgba8();
+    assert_eq!(
+        reference.dimensions(),
+        actual.dimensions(),
+        "{name}: size differs from the reference"
+    );
+
+    let mut diff = RgbaImage::new(actual.width(), actual.height());
+    let mut mismatched = 0;
+    for ((expected, got), marker) in reference
+        .pixels()
+        .zip(actual.pixels())
+        .zip(diff.pixels_mut())
+    {
+        let matches = expected
+            .0
+            .iter()
+            .zip(got.0)
+            .all(|(&e, g)| e.abs_diff(g) <= CHANNEL_TOLERANCE);
+        *marker = if matches {
+            Rgba([0, 0, 0, 255])
+        } else {
+            mismatched += 1;
+            Rgba([255, 0, 255, 255])
+        };
+    }
+
+    let share = mismatched as f64 / (actual.width() * actual.height()) as f64;
+    if share > MISMATCH_TOLERANCE {
+        actual.save(&actual_path).unwrap();
+        diff.save(&diff_path).unwrap();
+        panic!(
+            "{name}: {mismatched} pixels ({:.2}%) differ from {}, see {}",
+            share * 100.0,
+            reference_path.display(),
+            diff_path.display()
+        );
+    }
+}
+
+/// Writes a generated input into a temporary directory, unique per test.
+fn fixture(name: &str, write: impl FnOnce(&Path)) -> PathBuf {
+    let dir = env::temp_dir().join(format!("aphrodite-golden-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(name);
+    write(&path);
+    path
+}
+
+fn wallpaper(scene: SceneKind, path: &Path, fit: FitMode) -> WallpaperConfig {
+    WallpaperConfig {
+        scene,
+        path: Some(path.to_owned()),
+        fit,
+        background: "#203040".parse::<Color>().unwrap(),
+        // Keeps the tests out of the cache of the user
+        cache: false,
+        ..Default::default()
+    }
+}
+
+/// A 32x16 gradient with a marker in the top left corner, to catch flipped or cropped output.
+/// Wider than the target, so fill, fit and stretch each place it differently.
+fn gradient() -> RgbaImage {
+    RgbaImage::from_fn(32, 16, |x, y| {
+        if x < 4 && y < 4 {
+            Rgba([255, 255, 255, 255])
+        } else {
+            Rgba([(x * 8) as u8, (y * 10) as u8, 128, 255])
+        }
+    })
+}
+
+/// Single pixel black and white squares four times the size of the target, which any
+/// downscaling blurs into gray.
+fn checkerboard() -> RgbaImage {
+    RgbaImage::from_fn(WIDTH * 4, HEIGHT * 4, |x, y| {
+        let v = if (x + y) % 2 == 0 { 255 } else { 0 };
+        Rgba([v, v, v, 255])
+    })
+}
+
+fn solid(color: [u8; 3]) -> RgbaImage {
+    RgbaImage::from_pixel(16, 16, Rgba([color[0], color[1], color[2], 255]))
+}
+
+/// Red, green and blue for 100ms each, played twice.
+fn write_rgb_gif(path: &Path) {
+    let file = fs::File::create(path).unwrap();
+    let mut encoder = GifEncoder::new(file);
+    encoder
+        .set_repeat(image::codecs::gif::Repeat::Finite(1))
+        .unwrap();
+    encoder
+        .encode_frames(
+            [[255, 0, 0], [0, 255, 0], [0, 0, 255]].map(|color| {
+                Frame::from_parts(solid(color), 0, 0, Delay::from_numer_denom_ms(100, 1))
+            }),
+        )
+        .unwrap();
+}
+
+#[test]
+fn none_scene() {
+    let Some(harness) = Harness::new() else {
+        return;
+    };
+    harness.check(&SceneType::None, "none");
+}
+
+#[test]
+fn image_scene() {
+    let Some(harness) = Harness::new() else {
+        return;
+    };
+    let path = fixture("gradient.png", |path| gradient().save(path).unwrap());
+
+    for (fit, name) in [
+        (FitMode::Fill, "image_fill"),
+        (FitMode::Fit, "image_fit"),
+        (FitMode::Center, "image_center"),
+        (FitMode::Stretch, "image_stretch"),
+        (FitMode::Tile, "image_tile"),
+    ] {
+        let scene = harness.load(&wallpaper(SceneKind::Image, &path, fit));
+        harness.check(&scene, name);
+    }
+}
+
+/// The same image decoded on a worker thread and uploaded afterwards, as the daemon does.
+#[test]
+fn decoded_in_background() {
+    let Some(harness) = Harness::new() else {
+        return;
+    };
+    let path = fixture("gradient-decoded.png", |path| {
+        gradient().save(path).unwrap()
+    });
+    let config = wallpaper(SceneKind::Image, &path, FitMode::Fill);
+
+    let decoder = {
+        let config = config.clone();
+        std::thread::spawn(move || SceneType::decode(&config, None))
+    };
+    let decoded = decoder.join().unwrap().expect("failed to decode the image");
+    let mut scene = SceneType::from_decoded(&harness.core, &config, decoded).unwrap();
+    scene.resize(&harness.core, WIDTH, HEIGHT);
+    harness.check(&scene, "image_fill");
+}
+
+/// A fine checkerboard four times larger than the target, which aliases into stripes and
+/// moiré without filtering.
+#[test]
+fn downscaled_image() {
+    let Some(harness) = Harness::new() else {
+        return;
+    };
+    let path = fixture("checkerboard.png", |path| {
+        checkerboard().save(path).unwrap()
+    });
+
+    for (downscale, name) in [
+        (Downscale::Mipmap, "downscale_mipmap"),
+        (Downscale::Lanczos, "downscale_lanczos"),
+        (Downscale::Box, "downscale_box"),
+    ] {
+        let mut config = wallpaper(SceneKind::Image, &path, FitMode::Fill);
+        config.downscale = downscale;
+        let scene = harness.load(&config);
+        harness.check(&scene, name);
+    }
+}
+
+/// The checkerboard shrunk to the size of the target while decoding, before the upload.
+#[test]
+fn decoded_at_target_size() {
+    let Some(harness) = Harness::new() else {
+        return;
+    };
+    let path = fixture("checkerboard-decoded.png", |path| {
+        checkerboard().save(path).unwrap()
+    });
+    let config = wallpaper(SceneKind::Image, &path, FitMode::Fill);
+
+    let decoded = SceneType::decode(&config, Some((WIDTH, HEIGHT))).unwrap();
+    let DecodedImage::Still(img) = &decoded else {
+        panic!("decoded a still image as an animation");
+    };
+    assert_eq!((img.width(), img.height()), (WIDTH, HEIGHT));
+
+    let mut scene = SceneType::from_decoded(&harness.core, &config, decoded).unwrap();
+    scene.resize(&harness.core, WIDTH, HEIGHT);
+    harness.check(&scene, "downscale_decoded");
+}
+
+/// The first decode fills the cache, the second one is read back from it.
+#[test]
+fn cached_image() {
+    let Some(harness) = Harness::new() else {
+        return;
+    };
+    let dir = fixture("cache", |dir| fs::create_dir_all(dir).unwrap());
+    let cache = ImageCache::new(dir.clone());
+    let mut bytes = Vec::new();
+    gradient()
+        .write_to(
+            &mut io::Cursor::new(&mut bytes),
+            image::ImageOutputFormat::Png,
+        )
+        .unwrap();
+
+    let target = Some((WIDTH, HEIGHT));
+    let decoded = decode_still(
+        &bytes,
+        FitMode::Fill,
+        Downscale::Mipmap,
+        target,
+        Some(&cache),
+    );
+    assert_eq!(decoded.unwrap().to_rgba8(), gradient());
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+    let key = ImageCache::key(&bytes, FitMode::Fill, Downscale::Mipmap, (WIDTH, HEIGHT));
+    let cached = cache.load(&key).expect("the image was not cached");
+    assert_eq!(cached, gradient());
+
+    let img = image::DynamicImage::ImageRgba8(cached);
+    let mut scene = ImageScene::new(
+        &harness.core,
+        &img,
+        FitMode::Fill,
+        Downscale::Mipmap,
+        wgpu::Color::BLACK,
+    )
+    .map(SceneType::ImageBackground)
+    .unwrap();
+    scene.resize(&harness.core, WIDTH, HEIGHT);
+    harness.check(&scene, "image_fill");
+}
+
+/// A panorama wider than the largest texture of the adapter, which is downscaled on upload.
+#[test]
+fn oversized_image() {
+    let Some(harness) = Harness::new() else {
+        return;
+    };
+    let width = harness.core.device.limits().max_texture_dimension_2d * 3 / 2;
+    let path = fixture("panorama.png", |path| {
+        RgbaImage::from_fn(width, HEIGHT, |x, y| {
+            Rgba([(x * 255 / width) as u8, (y * 5) as u8, 128, 255])
+        })
+        .save(path)
+        .unwrap()
+    });
+
+    let scene = harness.load(&wallpaper(SceneKind::Image, &path, FitMode::Stretch));
+    harness.check(&scene, "oversized_image");
+}
+
+#[test]
+fn animated_scene() {
+    let Some(harness) = Harness::new() else {
+        return;
+    };
+    let path = fixture("rgb.gif", write_rgb_gif);
+    let mut scene = harness.load(&wallpaper(SceneKind::Animated, &path, FitMode::Fit));
+
+    // The first update starts the clock of the animation
+    for (millis, name) in [
+        (0, "animated_0ms"),
+        (150, "animated_150ms"),
+        (250, "animated_250ms"),
+        // Past both plays, the last frame stays
+        (1000, "animated_1000ms"),
+    ] {
+        harness.update(&mut scene, millis);
+        harness.check(&scene, name);
+    }
+    assert!(!scene.is_animated());
+}
+
+#[test]
+fn shader_scene() {
+    let Some(harness) = Harness::new() else {
+        return;
+    };
+    let path = fixture("waves.wgsl", |path| {
+        fs::write(path, include_str!("../../tests/golden/waves.wgsl")).unwrap()
+    });
+    let mut scene = harness.load(&wallpaper(SceneKind::Shader, &path, FitMode::Fill));
+    let SceneType::ShaderBackground(shader) = &mut scene else {
+        unreachable!();
+    };
+    shader.set_date([2024.0, 6.0, 15.0, 43200.0]);
+    scene.set_mouse(16.0, 12.0);
+
+    for (millis, name) in [
+        (0, "shader_0ms"),
+        (500, "shader_500ms"),
+        (1500, "shader_1500ms"),
+    ] {
+        harness.update(&mut scene, millis);
+        harness.check(&scene, name);
+    }
+}
+
+#[test]
+fn slideshow_scene() {
+    let Some(harness) = Harness::new() else {
+        return;
+    };
+    let dir = fixture("slides", |dir| {
+        fs::create_dir_all(dir).unwrap();
+        for (name, color) in [
+            ("1-red.png", [255, 0, 0]),
+            ("2-green.png", [0, 255, 0]),
+            ("3-blue.png", [0, 0, 255]),
+        ] {
+            solid(color).save(dir.join(name)).unwrap();
+        }
+    });
+    let mut config = wallpaper(SceneKind::Slideshow, &dir, FitMode::Fit);
+    config.interval = 1;
+    let mut scene = harness.load(&config);
+
+    // The same images as the frames of the animation, so the same references
+    for (millis, name) in [
+        (0, "animated_0ms"),
+        // Preloads the next image
+        (500, "animated_0ms"),
+        (1000, "animated_150ms"),
+        (2000, "animated_250ms"),
+        // Starts over
+        (3000, "animated_0ms"),
+    ] {
+        harness.update(&mut scene, millis);
+        // The next image is shown once it is decoded
+        scene.wait_for_decoder(&harness.core);
+        harness.check(&scene, name);
+    }
+
+    let SceneType::SlideshowBackground(slideshow) = &mut scene else {
+        unreachable!();
+    };
+    slideshow.step(-1).unwrap();
+    scene.wait_for_decoder(&harness.core);
+    harness.check(&scene, "animated_250ms");
+}
+
+#[test]
+fn transitions() {
+    let Some(harness) = Harness::new() else {
+        return;
+    };
+    let red = fixture("red.png", |path| solid([255, 0, 0]).save(path).unwrap());
+    let gradient = fixture("transition-gradient.png", |path| {
+        gradient().save(path).unwrap()
+    });
+    let from = harness.load(&wallpaper(SceneKind::Image, &red, FitMode::Fill));
+
+    for (kind, name) in [
+        (TransitionKind::Crossfade, "transition_crossfade"),
+        (TransitionKind::Wipe, "transition_wipe"),
+        (TransitionKind::Circle, "transition_circle"),
+        (TransitionKind::Pixelate, "transition_pixelate"),
+        (TransitionKind::Dissolve, "transition_dissolve"),
+    ] {
+        let mut config = wallpaper(SceneKind::Image, &gradient, FitMode::Fill);
+        config.transition = kind;
+        config.transition_duration = 1.0;
+        config.transition_easing = Easing::Linear;
+        let to = harness.load(&config);
+
+        // Halfway through
+        let mut transition = Transition::new(&harness.core, &from, None, (WIDTH, HEIGHT), &config);
+        transition.update(&harness.core, harness.clock.at(0));
+        transition.update(&harness.core, harness.clock.at(500));
+        transition.draw(&harness.core, &harness.target.view, &to);
+        let actual = harness.target.read_pixels(&harness.core).unwrap();
+        compare(name, &actual);
+
+        // Done, showing only the new wallpaper
+        transition.update(&harness.core, harness.clock.at(1000));
+        assert!(transition.is_done());
+        transition.draw(&harness.core, &harness.target.view, &to);
+        let actual = harness.target.read_pixels(&harness.core).unwrap();
+        compare("image_fill", &actual);
+    }
+}
+
+#[test]
+fn video_scene() {
+    let available = |name| {
+        Command::new(name)
+            .arg("-version")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .is_ok_and(|status| status.success())
+    };
+    if !available("ffmpeg") || !available("ffprobe") {
+        eprintln!("Skipping golden-image test, ffmpeg is not installed");
+        return;
+    }
+    let Some(harness) = Harness::new() else {
+        return;
+    };
+
+    // A raw YUV 4:4:4 video, which ffmpeg reads without any codec: a left and right half
+    // in different colors
+    let path = fixture("halves.y4m", |path| {
+        let (width, height) = (32, 16);
+        let mut file = fs::File::create(path).unwrap();
+        writeln!(file, "YUV4MPEG2 W{width} H{height} F10:1 Ip A1:1 C444").unwrap();
+        writeln!(file, "FRAME").unwrap();
+        for (left, right) in [(82, 145), (90, 54), (240, 34)] {
+            for _ in 0..height {
+                let row: Vec<u8> = (0..width)
+                    .map(|x| if x < width / 2 { left } else { right })
+                    .collect();
+                file.write_all(&row).unwrap();
+            }
+        }
+    });
+    // Only the first frame is deterministic, later ones depend on how fast ffmpeg is. The
+    // colors depend on how ffmpeg converts from YUV, so they are compared loosely instead of
+    // against a reference.
+    let scene = harness.load(&wallpaper(SceneKind::Video, &path, FitMode::Fit));
+    let frame = harness.render(&scene);
+    let near = |(x, y): (u32, u32), expected: [u8; 3]| {
+        let got = frame.get_pixel(x, y).0;
+        assert!(
+            (0..3).all(|i| got[i].abs_diff(expected[i]) <= 48),
+            "pixel at {x},{y} is {got:?}, expected about {expected:?}"
+        );
+    };
+    // Fitted to 64x32, with the background above and below
+    near((WIDTH / 2, 2), [0x20, 0x30, 0x40]);
+    near((WIDTH / 2, HEIGHT - 3), [0x20, 0x30, 0x40]);
+    near((WIDTH / 4, HEIGHT / 2), [255, 0, 0]);
+    near((WIDTH * 3 / 4, HEIGHT / 2), [0, 255, 0]);
+}
+
+/// The software renderer against the same references. It scales with a different filter than
+/// the GPU, so only scenes that are not filtered are compared.
+#[test]
+fn shm_scenes() {
+    let clock = StepClock::new();
+    let render = |scene: &mut ShmScene| {
+        let mut canvas = vec![0; WIDTH as usize * HEIGHT as usize * 4];
+        scene.draw(&mut canvas);
+        // ARGB8888 is stored as BGRA, and fully opaque here so premultiplying changed nothing
+        for pixel in canvas.chunks_exact_mut(4) {
+            pixel.swap(0, 2);
+        }
+        RgbaImage::from_raw(WIDTH, HEIGHT, canvas).unwrap()
+    };
+
+    let path = fixture("shm-gradient.png", |path| gradient().save(path).unwrap());
+    for (fit, name) in [
+        (FitMode::Center, "image_center"),
+        (FitMode::Tile, "image_tile"),
+    ] {
+        let mut scene = ShmScene::load(&wallpaper(SceneKind::Image, &path, fit)).unwrap();
+        scene.resize(WIDTH, HEIGHT);
+        compare(name, &render(&mut scene));
+    }
+
+    let path = fixture("shm-rgb.gif", write_rgb_gif);
+    let mut scene = ShmScene::load(&wallpaper(SceneKind::Animated, &path, FitMode::Fit)).unwrap();
+    scene.resize(WIDTH, HEIGHT);
+    for (millis, name) in [
+        (0, "animated_0ms"),
+        (150, "animated_150ms"),
+        (250, "animated_250ms"),
+        (1000, "animated_1000ms"),
+    ] {
+        scene.update(clock.at(millis));
+        compare(name, &render(&mut scene));
+    }
+    assert!(!scene.is_animated());
+}
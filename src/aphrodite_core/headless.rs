@@ -0,0 +1,98 @@
+use std::sync::mpsc;
+
+use color_eyre::eyre::{eyre, Result};
+use image::RgbaImage;
+
+use super::engine::{EngineCore, SceneType};
+
+/// A texture to render into without a compositor, e.g. for thumbnails or `aphrodite render`.
+pub struct OffscreenTarget {
+    pub texture: wgpu::Texture,
+    pub view: wgpu::TextureView,
+}
+
+impl OffscreenTarget {
+    pub fn new(core: &EngineCore, width: u32, height: u32) -> Self {
+        let texture = core.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Offscreen target"),
+            size: wgpu::Extent3d {
+                width: width.max(1),
+                height: height.max(1),
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            // The pipelines are built for this format
+            format: core.format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        Self { texture, view }
+    }
+
+    /// Copies the rendered pixels back into memory.
+    pub fn read_pixels(&self, core: &EngineCore) -> Result<RgbaImage> {
+        let size = self.texture.size();
+        let unpadded_row = size.width * 4;
+        // Buffer copies need rows aligned to 256 bytes
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_row = unpadded_row.div_ceil(align) * align;
+
+        let buffer = core.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Offscreen readback"),
+            size: padded_row as u64 * size.height as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = core.device.create_command_encoder(&Default::default());
+        encoder.copy_texture_to_buffer(
+            self.texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_row),
+                    rows_per_image: Some(size.height),
+                },
+            },
+            size,
+        );
+        core.queue.submit(Some(encoder.finish()));
+
+        let slice = buffer.slice(..);
+        let (sender, receiver) = mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        core.device.poll(wgpu::Maintain::Wait);
+        receiver.recv()??;
+
+        let mut pixels = Vec::with_capacity((unpadded_row * size.height) as usize);
+        for row in slice.get_mapped_range().chunks(padded_row as usize) {
+            pixels.extend_from_slice(&row[..unpadded_row as usize]);
+        }
+        buffer.unmap();
+
+        if matches!(
+            self.texture.format(),
+            wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb
+        ) {
+            for pixel in pixels.chunks_exact_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+
+        RgbaImage::from_raw(size.width, size.height, pixels)
+            .ok_or_else(|| eyre!("readback buffer does not match the texture size"))
+    }
+}
+
+impl EngineCore {
+    pub fn render_offscreen(&self, target: &OffscreenTarget, scene: &SceneType) {
+        self.draw_scene(&target.view, scene);
+    }
+}
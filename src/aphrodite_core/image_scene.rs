@@ -0,0 +1,240 @@
+use color_eyre::eyre::{eyre, Result};
+use image::{
+    imageops::{self, FilterType},
+    RgbaImage,
+};
+
+use super::cache::ImageCache;
+use super::engine::{EngineCore, SimpleImage, Vertex};
+use super::texture;
+use crate::config::{Downscale, FitMode};
+
+/// A static image covering the output according to a [`FitMode`].
+pub struct ImageScene {
+    pub image: SimpleImage,
+    pub image_size: (u32, u32),
+    pub fit: FitMode,
+    /// Clear color, visible around the image in the `fit` and `center` modes.
+    pub background: wgpu::Color,
+    /// Kept to resample from on the CPU for every new size, unless mipmaps are good enough.
+    source: Option<(RgbaImage, Downscale)>,
+}
+
+impl ImageScene {
+    pub fn new(
+        core: &EngineCore,
+        img: &image::DynamicImage,
+        fit: FitMode,
+        downscale: Downscale,
+        background: wgpu::Color,
+    ) -> Result<Self> {
+        let texture =
+            texture::Texture::from_image(core, img, Some("Wallpaper")).map_err(|e| eyre!(e))?;
+        let image_size = (img.width(), img.height());
+        // Center and tile never scale the image
+        let resamples = downscale != Downscale::Mipmap
+            && matches!(fit, FitMode::Fill | FitMode::Fit | FitMode::Stretch);
+
+        Ok(Self {
+            image: SimpleImage::new(core, texture, fit),
+            image_size,
+            fit,
+            background,
+            source: resamples.then(|| (img.to_rgba8(), downscale)),
+        })
+    }
+
+    /// Recomputes the quad for a new target size.
+    pub fn resize(&mut self, core: &EngineCore, width: u32, height: u32) {
+        if let Err(err) = self.resample(core, (width, height)) {
+            log::warn!("Failed to resample the wallpaper, using mipmaps: {err:?}");
+        }
+
+        let vertices = fit_quad(self.fit, self.image_size, (width, height));
+        core.queue.write_buffer(
+            &self.image.vertex_buffer,
+            0,
+            bytemuck::cast_slice(&vertices),
+        );
+    }
+
+    /// Replaces the texture with the source scaled to the size it is drawn at. Only shrinks,
+    /// enlarging is left to the sampler.
+    fn resample(&mut self, core: &EngineCore, target: (u32, u32)) -> Result<()> {
+        let Some((source, downscale)) = &self.source else {
+            return Ok(());
+        };
+        let (_, _, (width, height)) = placement(self.fit, source.dimensions(), target);
+        let size = (width.min(source.width()), height.min(source.height()));
+        let current = self.image.texture.texture.size();
+        if size == (current.width, current.height) {
+            return Ok(());
+        }
+
+        let scaled = if size == source.dimensions() {
+            source.clone()
+        } else {
+            resample(source, size, *downscale)
+        };
+        let img = image::DynamicImage::ImageRgba8(scaled);
+        let texture =
+            texture::Texture::from_image(core, &img, Some("Wallpaper")).map_err(|e| eyre!(e))?;
+
+        let sampler = SimpleImage::create_sampler(core, self.fit);
+        self.image.bind_group = SimpleImage::create_bind_group(core, &texture, &sampler);
+        self.image.texture = texture;
+        Ok(())
+    }
+
+    pub fn draw<'a>(&'a self, core: &'a EngineCore, pass: &mut wgpu::RenderPass<'a>) {
+        self.image.draw(core, pass);
+    }
+}
+
+/// Two triangles placing an image of `image` pixels onto a target of `target` pixels.
+pub fn fit_quad(fit: FitMode, image: (u32, u32), target: (u32, u32)) -> [Vertex; 6] {
+    let (iw, ih) = (image.0.max(1) as f32, image.1.max(1) as f32);
+    let (tw, th) = (target.0.max(1) as f32, target.1.max(1) as f32);
+
+    // Rectangle in target pixels (x, y, width, height) and the tex coords at its far corner
+    let (rect, uv_max) = match fit {
+        FitMode::Stretch => ((0.0, 0.0, tw, th), (1.0, 1.0)),
+        FitMode::Tile => ((0.0, 0.0, tw, th), (tw / iw, th / ih)),
+        FitMode::Center => (((tw - iw) / 2.0, (th - ih) / 2.0, iw, ih), (1.0, 1.0)),
+        FitMode::Fill | FitMode::Fit => {
+            let scale = if fit == FitMode::Fill {
+                (tw / iw).max(th / ih)
+            } else {
+                (tw / iw).min(th / ih)
+            };
+            let (w, h) = (iw * scale, ih * scale);
+            (((tw - w) / 2.0, (th - h) / 2.0, w, h), (1.0, 1.0))
+        }
+    };
+
+    let (x, y, w, h) = rect;
+    let left = x / tw * 2.0 - 1.0;
+    let right = (x + w) / tw * 2.0 - 1.0;
+    let top = 1.0 - y / th * 2.0;
+    let bottom = 1.0 - (y + h) / th * 2.0;
+    let (u, v) = uv_max;
+
+    let bottom_left = Vertex::new([left, bottom], [0.0, v]);
+    let bottom_right = Vertex::new([right, bottom], [u, v]);
+    let top_right = Vertex::new([right, top], [u, 0.0]);
+    let top_left = Vertex::new([left, top], [0.0, 0.0]);
+
+    [
+        bottom_left,
+        bottom_right,
+        top_right,
+        bottom_left,
+        top_right,
+        top_left,
+    ]
+}
+
+/// Top left corner and size in target pixels of an `image` placed onto `target`, the same
+/// rectangle [`fit_quad`] draws the image into.
+pub fn placement(fit: FitMode, image: (u32, u32), target: (u32, u32)) -> (i64, i64, (u32, u32)) {
+    let (iw, ih) = (image.0.max(1) as f64, image.1.max(1) as f64);
+    let (tw, th) = (target.0 as f64, target.1 as f64);
+
+    let (w, h) = match fit {
+        FitMode::Stretch => (tw, th),
+        // Tiles start in the top left corner
+        FitMode::Tile => return (0, 0, image),
+        FitMode::Center => (iw, ih),
+        FitMode::Fill => {
+            let scale = (tw / iw).max(th / ih);
+            (iw * scale, ih * scale)
+        }
+        FitMode::Fit => {
+            let scale = (tw / iw).min(th / ih);
+            (iw * scale, ih * scale)
+        }
+    };
+    let (w, h) = (w.round().max(1.0), h.round().max(1.0));
+
+    (
+        ((tw - w) / 2.0).round() as i64,
+        ((th - h) / 2.0).round() as i64,
+        (w as u32, h as u32),
+    )
+}
+
+/// Scales `image` to `size` on the CPU, with the filter `downscale` asks for when it gets
+/// smaller.
+pub fn resample(image: &RgbaImage, size: (u32, u32), downscale: Downscale) -> RgbaImage {
+    let shrinks = size.0 <= image.width() && size.1 <= image.height();
+    match cpu_filter(downscale, shrinks) {
+        Some(filter) => imageops::resize(image, size.0, size.1, filter),
+        None => imageops::thumbnail(image, size.0, size.1),
+    }
+}
+
+/// The filter `downscale` resamples with on the CPU. `None` averages every source pixel into
+/// the target pixel it falls into, which only works when the image `shrinks`.
+fn cpu_filter(downscale: Downscale, shrinks: bool) -> Option<FilterType> {
+    match downscale {
+        Downscale::Lanczos => Some(FilterType::Lanczos3),
+        Downscale::Box if shrinks => None,
+        // Mipmaps are only a thing on the GPU
+        Downscale::Box | Downscale::Mipmap => Some(FilterType::Triangle),
+    }
+}
+
+/// Shrinks a freshly decoded image to the size it is drawn at on a `target` of that many
+/// pixels, before it is converted to RGBA and uploaded. Center and tile draw images at their
+/// own size, so those are kept.
+pub fn shrink_for(
+    img: image::DynamicImage,
+    fit: FitMode,
+    downscale: Downscale,
+    target: (u32, u32),
+) -> image::DynamicImage {
+    if !matches!(fit, FitMode::Fill | FitMode::Fit | FitMode::Stretch) {
+        return img;
+    }
+    let (_, _, (width, height)) = placement(fit, (img.width(), img.height()), target);
+    let size = (width.min(img.width()), height.min(img.height()));
+    if size == (img.width(), img.height()) {
+        return img;
+    }
+
+    // Never larger than the image, see above
+    match cpu_filter(downscale, true) {
+        Some(filter) => img.resize_exact(size.0, size.1, filter),
+        None => img.thumbnail_exact(size.0, size.1),
+    }
+}
+
+/// Decodes a still image, shrunk with [`shrink_for`] when there is a `target`. Images prepared
+/// for the same target before are read from `cache` instead, and new ones are stored there.
+pub fn decode_still(
+    bytes: &[u8],
+    fit: FitMode,
+    downscale: Downscale,
+    target: Option<(u32, u32)>,
+    cache: Option<&ImageCache>,
+) -> Result<image::DynamicImage> {
+    let Some(target) = target else {
+        return Ok(image::load_from_memory(bytes)?);
+    };
+    let key = cache.map(|_| ImageCache::key(bytes, fit, downscale, target));
+    if let (Some(cache), Some(key)) = (cache, &key) {
+        if let Some(img) = cache.load(key) {
+            return Ok(image::DynamicImage::ImageRgba8(img));
+        }
+    }
+
+    let img = shrink_for(image::load_from_memory(bytes)?, fit, downscale, target);
+    let (Some(cache), Some(key)) = (cache, &key) else {
+        return Ok(img);
+    };
+    let img = img.into_rgba8();
+    if let Err(err) = cache.store(key, &img) {
+        log::warn!("Failed to cache the wallpaper: {err:#}");
+    }
+    Ok(image::DynamicImage::ImageRgba8(img))
+}
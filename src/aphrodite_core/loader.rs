@@ -0,0 +1,249 @@
+//! Decodes image wallpapers on worker threads, so the event loop keeps drawing the current
+//! wallpaper of every output while a large file is loaded. Only the upload to the GPU, or the
+//! copy into shared memory, happens on the event loop, once the pixels are handed back over a
+//! channel.
+
+use std::thread;
+
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use smithay_client_toolkit::reexports::calloop::{
+    channel::{self, Channel, Sender},
+    LoopHandle,
+};
+use wayland_client::QueueHandle;
+
+use super::engine::{DecodedImage, EngineShell, SceneType};
+use super::shm::{ShmScene, ShmSurface};
+use crate::config::WallpaperConfig;
+
+/// A wallpaper on its way to an output, see [`EngineShell::load_in_background`].
+pub struct Loading {
+    /// Shared by all outputs waiting for the same decoder.
+    pub id: u64,
+    /// Size the image is decoded for, the largest of the outputs sharing the load.
+    pub target: (u32, u32),
+    pub wallpaper: WallpaperConfig,
+}
+
+/// A wallpaper decoded for the outputs loading it under `id`.
+pub struct Decoded {
+    /// Matched against [`Loading::id`], to drop loads that were replaced while they ran.
+    id: u64,
+    target: (u32, u32),
+    image: Result<DecodedImage>,
+    /// Size of the image before it was shrunk to fit into a texture, if it was.
+    placed_as: Option<(u32, u32)>,
+}
+
+/// Hands decoded wallpapers to [`EngineShell::finish_load`] on the event loop of the shell.
+pub fn bind(
+    loop_handle: &LoopHandle<'static, EngineShell>,
+    qh: &QueueHandle<EngineShell>,
+) -> Result<Sender<Decoded>> {
+    let (sender, decoded): (Sender<Decoded>, Channel<Decoded>) = channel::channel();
+
+    let qh = qh.clone();
+    loop_handle
+        .insert_source(decoded, move |event, _, shell| {
+            if let channel::Event::Msg(decoded) = event {
+                shell.finish_load(&qh, decoded);
+            }
+        })
+        .map_err(|err| eyre!(err.error))?;
+
+    Ok(sender)
+}
+
+impl EngineShell {
+    /// Starts decoding an image or animated wallpaper for an output, at the size of the
+    /// output. The current scene is drawn until the new one is ready, then `wallpaper`
+    /// replaces the one of the output. Loads started before for the same output are dropped.
+    ///
+    /// Outputs loading the same file share one decoder, which prepares the image for the
+    /// largest of them. It is started over when a larger output joins.
+    pub fn load_in_background(&mut self, index: usize, wallpaper: WallpaperConfig) -> Result<()> {
+        // Reading the header is quick and reports missing or unsupported files right away
+        if let Some(path) = &wallpaper.path {
+            image::image_dimensions(path)
+                .wrap_err_with(|| format!("failed to load {}", path.display()))?;
+        }
+
+        let (width, height) = self.outputs[index].size;
+        let shared = self
+            .outputs
+            .iter()
+            .enumerate()
+            .filter(|&(other, _)| other != index)
+            .find_map(|(_, output)| {
+                let loading = output.loading.as_ref()?;
+                SceneType::decodes_alike(&loading.wallpaper, &wallpaper)
+                    .then_some((loading.id, loading.target))
+            });
+        if let Some((id, target)) = shared {
+            if width <= target.0 && height <= target.1 {
+                self.outputs[index].loading = Some(Loading {
+                    id,
+                    target,
+                    wallpaper,
+                });
+                return Ok(());
+            }
+        }
+
+        let target = match shared {
+            Some((_, (shared_width, shared_height))) => {
+                (width.max(shared_width), height.max(shared_height))
+            }
+            None => (width, height),
+        };
+        let id = self.next_load;
+        self.next_load += 1;
+        let sender = self.decoder.clone();
+        let config = wallpaper.clone();
+        let limit = self
+            .core
+            .as_ref()
+            .map(|core| core.device.limits().max_texture_dimension_2d);
+        thread::Builder::new()
+            .name("aphrodite-decoder".into())
+            .spawn(move || {
+                let mut image = SceneType::decode(&config, Some(target));
+                let placed_as = match (&mut image, limit) {
+                    (Ok(image), Some(limit)) => image.shrink_to_limit(limit),
+                    _ => None,
+                };
+                // The event loop is gone when the daemon is shutting down
+                let _ = sender.send(Decoded {
+                    id,
+                    target,
+                    image,
+                    placed_as,
+                });
+            })
+            .wrap_err("failed to start the decoder thread")?;
+
+        // The outputs waiting for the smaller image wait for this one instead
+        for output in &mut self.outputs {
+            if let Some(loading) = &mut output.loading {
+                if shared.is_some_and(|(shared_id, _)| shared_id == loading.id) {
+                    loading.id = id;
+                    loading.target = target;
+                }
+            }
+        }
+        self.outputs[index].loading = Some(Loading {
+            id,
+            target,
+            wallpaper,
+        });
+
+        Ok(())
+    }
+
+    /// Uploads a decoded wallpaper and shows it on every output still waiting for it.
+    fn finish_load(&mut self, qh: &QueueHandle<Self>, decoded: Decoded) {
+        let waiting: Vec<usize> = (0..self.outputs.len())
+            .filter(|&index| {
+                self.outputs[index]
+                    .loading
+                    .as_ref()
+                    .is_some_and(|loading| loading.id == decoded.id)
+            })
+            .collect();
+        let image = match decoded.image {
+            Ok(image) => image,
+            Err(err) => {
+                log::error!("Failed to load the wallpaper: {err:?}");
+                for &index in &waiting {
+                    self.outputs[index].loading = None;
+                }
+                return;
+            }
+        };
+
+        let mut image = Some(image);
+        for (i, &index) in waiting.iter().enumerate() {
+            // Only the outputs before the last one need a copy
+            let image = if i + 1 < waiting.len() {
+                image.clone()
+            } else {
+                image.take()
+            };
+            if let Some(image) = image {
+                self.show_decoded(qh, index, decoded.target, image, decoded.placed_as);
+            }
+        }
+    }
+
+    /// Decodes the wallpaper of an output again when it grew beyond the size the image was
+    /// decoded for, so it is not drawn enlarged.
+    pub fn decode_for_new_size(&mut self, index: usize) {
+        let output = &self.outputs[index];
+        let (width, height) = output.size;
+        let grew = output
+            .decoded_for
+            .is_some_and(|(decoded_width, decoded_height)| {
+                width > decoded_width || height > decoded_height
+            });
+        if !grew {
+            return;
+        }
+
+        let wallpaper = output.latest_wallpaper().clone();
+        if let Err(err) = self.load_in_background(index, wallpaper) {
+            log::warn!("Failed to decode the wallpaper for the new size: {err:?}");
+        }
+    }
+
+    fn show_decoded(
+        &mut self,
+        qh: &QueueHandle<Self>,
+        index: usize,
+        target: (u32, u32),
+        image: DecodedImage,
+        placed_as: Option<(u32, u32)>,
+    ) {
+        let output = &mut self.outputs[index];
+        let Some(loading) = output.loading.take() else {
+            return;
+        };
+        let (width, height) = output.size;
+        let still = matches!(image, DecodedImage::Still(_));
+
+        if self.shm.is_some() {
+            let mut scene = ShmScene::from_decoded(&loading.wallpaper, image);
+            scene.resize(width, height);
+            scene.set_paused(output.paused);
+            output.shm_surface = Some(ShmSurface::new(scene));
+            output.wallpaper = loading.wallpaper;
+            output.decoded_for = still.then_some(target);
+            self.draw_output(qh, index);
+            return;
+        }
+
+        let Some(core) = &self.core else {
+            return;
+        };
+        let mut scene = match SceneType::from_decoded(core, &loading.wallpaper, image) {
+            Ok(scene) => scene,
+            Err(err) => {
+                log::error!("Failed to load the wallpaper: {err:?}");
+                return;
+            }
+        };
+        if let Some(size) = placed_as {
+            scene.place_as(size);
+        }
+        scene.resize(core, width, height);
+        scene.set_paused(output.paused);
+        output.wallpaper = loading.wallpaper;
+        output.decoded_for = still.then_some(target);
+
+        // The first wallpaper of an output appears without a transition
+        if !matches!(output.scene, SceneType::None) {
+            self.begin_transition(index);
+        }
+        self.outputs[index].scene = scene;
+        self.draw_output(qh, index);
+    }
+}
@@ -1,3 +1,18 @@
 pub mod engine;
 pub mod connection;
-pub mod texture;
\ No newline at end of file
+pub mod control;
+pub mod headless;
+pub mod animation;
+pub mod cache;
+pub mod image_scene;
+pub mod loader;
+pub mod scale;
+pub mod shader;
+pub mod shm;
+pub mod slideshow;
+pub mod texture;
+pub mod transition;
+pub mod video;
+
+#[cfg(test)]
+mod golden;
\ No newline at end of file
@@ -0,0 +1,106 @@
+use smithay_client_toolkit::{delegate_simple, registry::SimpleGlobal};
+use wayland_client::{
+    globals::GlobalList, protocol::wl_surface::WlSurface, Connection, Dispatch, QueueHandle,
+};
+use wayland_protocols::wp::{
+    fractional_scale::v1::client::{
+        wp_fractional_scale_manager_v1::WpFractionalScaleManagerV1,
+        wp_fractional_scale_v1::{self, WpFractionalScaleV1},
+    },
+    viewporter::client::{wp_viewport::WpViewport, wp_viewporter::WpViewporter},
+};
+
+use super::engine::EngineShell;
+
+/// Scale of a surface in 120ths, the unit used by wp-fractional-scale-v1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Scale(pub u32);
+
+impl Scale {
+    pub fn from_integer(factor: i32) -> Self {
+        Scale(factor.max(1) as u32 * 120)
+    }
+
+    /// Integer buffer scale to use when the compositor has no fractional scaling.
+    pub fn integer(self) -> i32 {
+        (self.0 / 120).max(1) as i32
+    }
+
+    /// Converts a size in surface-local coordinates into buffer pixels.
+    pub fn to_physical(self, logical: u32) -> u32 {
+        (logical * self.0 + 60) / 120
+    }
+}
+
+/// Optional globals used to render at the native pixel density of an output.
+pub struct ScaleState {
+    viewporter: Option<SimpleGlobal<WpViewporter, 1>>,
+    fractional_scale: Option<SimpleGlobal<WpFractionalScaleManagerV1, 1>>,
+}
+
+impl ScaleState {
+    pub fn bind(globals: &GlobalList, qh: &QueueHandle<EngineShell>) -> Self {
+        let viewporter = SimpleGlobal::bind(globals, qh).ok();
+        let fractional_scale = SimpleGlobal::bind(globals, qh).ok();
+        if fractional_scale.is_none() {
+            log::info!("wp-fractional-scale-v1 not available, using integer buffer scale");
+        }
+
+        Self {
+            viewporter,
+            fractional_scale,
+        }
+    }
+
+    /// Viewport of a surface, used to map a buffer of any size onto its logical size.
+    pub fn viewport(
+        &self,
+        qh: &QueueHandle<EngineShell>,
+        surface: &WlSurface,
+    ) -> Option<WpViewport> {
+        let viewporter = self.viewporter.as_ref()?.get().ok()?;
+        Some(viewporter.get_viewport(surface, qh, ()))
+    }
+
+    /// Fractional scale object for a surface. Fractional scaling also needs the viewport of the
+    /// surface, which is what maps the larger buffer back onto the logical size.
+    pub fn fractional_scale(
+        &self,
+        qh: &QueueHandle<EngineShell>,
+        surface: &WlSurface,
+    ) -> Option<WpFractionalScaleV1> {
+        let manager = self.fractional_scale.as_ref()?.get().ok()?;
+        Some(manager.get_fractional_scale(surface, qh, surface.clone()))
+    }
+}
+
+impl Dispatch<WpFractionalScaleV1, WlSurface> for EngineShell {
+    fn event(
+        state: &mut Self,
+        _proxy: &WpFractionalScaleV1,
+        event: wp_fractional_scale_v1::Event,
+        surface: &WlSurface,
+        conn: &Connection,
+        qh: &QueueHandle<Self>,
+    ) {
+        if let wp_fractional_scale_v1::Event::PreferredScale { scale } = event {
+            state.set_scale(conn, qh, surface, Scale(scale));
+        }
+    }
+}
+
+impl Dispatch<WpViewport, ()> for EngineShell {
+    fn event(
+        _state: &mut Self,
+        _proxy: &WpViewport,
+        _event: <WpViewport as wayland_client::Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // wp_viewport has no events
+    }
+}
+
+delegate_simple!(EngineShell, WpViewporter, 1);
+delegate_simple!(EngineShell, WpFractionalScaleManagerV1, 1);
@@ -0,0 +1,284 @@
+//! Shadertoy-style wallpapers: a WGSL file defining `fs_main`, drawn over the whole output.
+//!
+//! ```wgsl
+//! @fragment
+//! fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+//!     let wave = 0.5 + 0.5 * sin(uniforms.time + in.tex_coords.x * 6.28);
+//!     return vec4<f32>(in.tex_coords, wave, 1.0);
+//! }
+//! ```
+//!
+//! The vertex stage, `VertexOutput` and the `uniforms` binding come from `shader.wgsl`.
+//!
+//! The file is watched while the wallpaper runs and recompiled when it changes. A shader that
+//! fails to compile is reported and the previous one keeps running.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+use chrono::{Datelike, Timelike};
+use color_eyre::eyre::{bail, eyre, Result, WrapErr};
+use wgpu::util::DeviceExt;
+
+use super::engine::{quad_pipeline, EngineCore};
+use super::image_scene::fit_quad;
+use crate::config::FitMode;
+
+const PRELUDE: &str = include_str!("shader.wgsl");
+
+/// How often the shader file is checked for changes.
+const RELOAD_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Mirrors `Uniforms` in `shader.wgsl`, including its padding.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct ShaderUniforms {
+    resolution: [f32; 2],
+    mouse: [f32; 2],
+    time: f32,
+    time_delta: f32,
+    frame: u32,
+    _padding: u32,
+    date: [f32; 4],
+}
+
+pub struct ShaderScene {
+    path: PathBuf,
+    pipeline: wgpu::RenderPipeline,
+    pipeline_layout: wgpu::PipelineLayout,
+    /// Modification time of the file the pipeline was compiled from.
+    modified: Option<SystemTime>,
+    last_reload_check: Option<Instant>,
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    vertex_buffer: wgpu::Buffer,
+
+    uniforms: ShaderUniforms,
+    last_frame: Option<Instant>,
+    /// Replaces the local date, so renders do not depend on the wall clock.
+    fixed_date: Option<[f32; 4]>,
+    paused: bool,
+}
+
+impl ShaderScene {
+    pub fn new(core: &EngineCore, path: &Path) -> Result<Self> {
+        let bind_group_layout =
+            core.device
+                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    label: Some("Shader uniforms"),
+                    entries: &[wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    }],
+                });
+        let pipeline_layout = core
+            .device
+            .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Shader wallpaper"),
+                bind_group_layouts: &[&bind_group_layout],
+                push_constant_ranges: &[],
+            });
+        let modified = modified(path);
+        let pipeline = compile(core, &pipeline_layout, path)?;
+
+        let uniforms = ShaderUniforms::default();
+        let uniform_buffer = core
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Shader uniforms"),
+                contents: bytemuck::bytes_of(&uniforms),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            });
+        let bind_group = core.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Shader uniforms"),
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+        });
+
+        // The shader decides what to draw where, so the quad always covers the whole output
+        let vertices = fit_quad(FitMode::Stretch, (1, 1), (1, 1));
+        let vertex_buffer = core
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Shader quad"),
+                contents: bytemuck::cast_slice(&vertices),
+                usage: wgpu::BufferUsages::VERTEX,
+            });
+
+        Ok(Self {
+            path: path.to_owned(),
+            pipeline,
+            pipeline_layout,
+            modified,
+            last_reload_check: None,
+            uniform_buffer,
+            bind_group,
+            vertex_buffer,
+            uniforms,
+            last_frame: None,
+            fixed_date: None,
+            paused: false,
+        })
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.uniforms.resolution = [width as f32, height as f32];
+    }
+
+    /// Pointer position in pixels of the output.
+    pub fn set_mouse(&mut self, x: f32, y: f32) {
+        self.uniforms.mouse = [x, y];
+    }
+
+    #[cfg(test)]
+    pub fn set_date(&mut self, date: [f32; 4]) {
+        self.fixed_date = Some(date);
+    }
+
+    pub fn is_playing(&self) -> bool {
+        !self.paused
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+        // Time does not advance while paused
+        self.last_frame = None;
+    }
+
+    /// Advances the clock and uploads the uniforms for the next frame.
+    pub fn update(&mut self, core: &EngineCore, now: Instant) -> bool {
+        if self.paused {
+            return false;
+        }
+        self.reload_if_changed(core, now);
+
+        let delta = self
+            .last_frame
+            .map_or(0.0, |last| now.duration_since(last).as_secs_f32());
+        self.last_frame = Some(now);
+
+        let uniforms = &mut self.uniforms;
+        uniforms.time += delta;
+        uniforms.time_delta = delta;
+        uniforms.frame = uniforms.frame.wrapping_add(1);
+        uniforms.date = self.fixed_date.unwrap_or_else(local_date);
+
+        core.queue
+            .write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&self.uniforms));
+
+        true
+    }
+
+    /// Recompiles the shader when its file changed, keeping the old pipeline on errors.
+    fn reload_if_changed(&mut self, core: &EngineCore, now: Instant) {
+        if self
+            .last_reload_check
+            .is_some_and(|last| now.duration_since(last) < RELOAD_INTERVAL)
+        {
+            return;
+        }
+        self.last_reload_check = Some(now);
+
+        let modified = modified(&self.path);
+        // Editors may briefly remove the file while saving, wait until it is back
+        if modified.is_none() || modified == self.modified {
+            return;
+        }
+        self.modified = modified;
+
+        match compile(core, &self.pipeline_layout, &self.path) {
+            Ok(pipeline) => {
+                log::info!("Reloaded {}", self.path.display());
+                self.pipeline = pipeline;
+            }
+            Err(err) => log::error!(
+                "Failed to reload {}, keeping the previous shader:\n{err}",
+                self.path.display()
+            ),
+        }
+    }
+
+    pub fn draw<'a>(&'a self, pass: &mut wgpu::RenderPass<'a>) {
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        pass.draw(0..6, 0..1);
+    }
+}
+
+/// Reads a shader wallpaper and checks it with naga, without a GPU. Returns the source
+/// together with the prelude. Errors carry the report of naga, pointing into `path`.
+pub fn validate(path: &Path) -> Result<String> {
+    let code =
+        fs::read_to_string(path).wrap_err_with(|| format!("failed to read {}", path.display()))?;
+    let source = format!("{code}\n{PRELUDE}");
+    let name = path.display().to_string();
+
+    let module = naga::front::wgsl::parse_str(&source)
+        .map_err(|err| eyre!(err.emit_to_string_with_path(&source, &name)))?;
+    naga::valid::Validator::new(
+        naga::valid::ValidationFlags::all(),
+        naga::valid::Capabilities::empty(),
+    )
+    .validate(&module)
+    .map_err(|err| eyre!(err.emit_to_string_with_path(&source, &name)))?;
+
+    Ok(source)
+}
+
+/// Compiles a shader wallpaper. Errors carry the report of naga, pointing into `path`.
+fn compile(
+    core: &EngineCore,
+    layout: &wgpu::PipelineLayout,
+    path: &Path,
+) -> Result<wgpu::RenderPipeline> {
+    // wgpu panics on invalid shaders, so they are checked with naga first
+    let source = validate(path)?;
+    let name = path.display().to_string();
+
+    // Catches what naga does not know about, like a missing or mismatched `fs_main`
+    core.device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let shader = core
+        .device
+        .create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some(&name),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        });
+    let pipeline = quad_pipeline(&core.device, core.format, &name, layout, &shader);
+    match pollster::block_on(core.device.pop_error_scope()) {
+        Some(wgpu::Error::Validation { description, .. }) => bail!("{name}: {description}"),
+        Some(err) => bail!("{name}: {err}"),
+        None => {}
+    }
+
+    Ok(pipeline)
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+/// The `date` uniform: year, month, day and seconds since midnight in local time.
+fn local_date() -> [f32; 4] {
+    let now = chrono::Local::now();
+    let seconds = now.num_seconds_from_midnight() as f32 + now.nanosecond() as f32 / 1e9;
+
+    [
+        now.year() as f32,
+        now.month() as f32,
+        now.day() as f32,
+        seconds,
+    ]
+}
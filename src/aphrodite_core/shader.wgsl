@@ -0,0 +1,42 @@
+// Appended to every shader wallpaper, which only has to define `fs_main`.
+// WGSL declarations are order independent, so putting this after the user's code keeps the
+// line numbers of compile errors in line with their file.
+
+struct Uniforms {
+    // Size of the output in pixels
+    resolution: vec2<f32>,
+    // Pointer position in pixels, from the top left corner
+    mouse: vec2<f32>,
+    // Seconds since the wallpaper started, not counting pauses
+    time: f32,
+    // Seconds since the previous frame
+    time_delta: f32,
+    frame: u32,
+    // Local year, month (1-12), day (1-31) and seconds since midnight
+    date: vec4<f32>,
+}
+
+@group(0) @binding(0)
+var<uniform> uniforms: Uniforms;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+}
+
+struct VertexOutput {
+    // Pixel position of the fragment, from the top left corner
+    @builtin(position) clip_position: vec4<f32>,
+    // 0..1 across the output, from the top left corner
+    @location(0) tex_coords: vec2<f32>,
+}
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+) -> VertexOutput {
+    var out: VertexOutput;
+    out.tex_coords = model.tex_coords;
+    out.clip_position = vec4<f32>(model.position, 1.0);
+    return out;
+}
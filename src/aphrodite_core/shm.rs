@@ -0,0 +1,404 @@
+//! Software rendering into `wl_shm` buffers, for machines where wgpu can not get an adapter.
+//!
+//! Only images and animated images are supported. When the compositor supports wp_viewporter,
+//! `fill` and `stretch` wallpapers are uploaded once at the size they were decoded at and the
+//! compositor crops and scales them, sharing the buffers between all outputs showing the same
+//! file. Everything else, and animations too long to keep every frame in a buffer, is scaled to
+//! the output on the CPU and copied into a buffer whenever it changes.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    rc::{Rc, Weak},
+    time::{Duration, Instant},
+};
+
+use color_eyre::eyre::{bail, Result, WrapErr};
+use image::RgbaImage;
+use smithay_client_toolkit::shm::{slot::Buffer, slot::SlotPool, Shm};
+use wayland_client::protocol::{wl_shm, wl_surface::WlSurface};
+use wayland_protocols::wp::viewporter::client::wp_viewport::WpViewport;
+
+use super::animation::{FrameSequence, Playback};
+use super::engine::{DecodedImage, SceneType};
+use super::image_scene::{placement, resample};
+use super::scale::Scale;
+use crate::config::{Color, Downscale, FitMode, SceneKind, WallpaperConfig};
+
+/// Scaled frames are kept for reuse as long as they fit into this many bytes, larger animations
+/// are scaled again for every frame.
+const SCALED_CACHE_LIMIT: usize = 256 << 20;
+/// Native frames of all files together are kept in buffers up to this many bytes, files that
+/// do not fit anymore are scaled on the CPU for every frame instead.
+const NATIVE_BUFFER_LIMIT: usize = 256 << 20;
+
+/// Shared memory all software rendered outputs draw into.
+pub struct EngineSHM {
+    pool: SlotPool,
+    /// Files uploaded at the size they were decoded at, as long as an output shows them.
+    native: HashMap<(PathBuf, (u32, u32)), Weak<NativeFrames>>,
+}
+
+/// Every frame of a file in its own buffer. The buffers are never written again, so they can be
+/// attached to any number of surfaces.
+struct NativeFrames {
+    buffers: Vec<Buffer>,
+    size: (u32, u32),
+}
+
+impl NativeFrames {
+    fn byte_len(&self) -> usize {
+        self.buffers.len() * self.size.0 as usize * self.size.1 as usize * 4
+    }
+}
+
+impl EngineSHM {
+    pub fn new(shm: &Shm) -> Result<Self> {
+        // Grows with the first buffers, which are sized by the outputs
+        let pool = SlotPool::new(4096, shm).wrap_err("failed to create the wl_shm pool")?;
+
+        Ok(Self {
+            pool,
+            native: HashMap::new(),
+        })
+    }
+
+    /// Draws the scene into a free buffer and attaches it to `surface`, or lets `viewport`
+    /// scale the native frame when the scene allows it. The caller commits.
+    pub fn present(
+        &mut self,
+        target: &mut ShmSurface,
+        surface: &WlSurface,
+        viewport: Option<&WpViewport>,
+        fractional: bool,
+        scale: Scale,
+        logical_size: (u32, u32),
+    ) -> Result<()> {
+        if let (Some(_), Some(path)) = (viewport, target.scene.viewport_path()) {
+            if target.native.is_none() {
+                target.native = self.upload(path, &target.scene)?;
+            }
+        }
+        if let (Some(viewport), Some(native)) = (viewport, &target.native) {
+            let (width, height) = native.size;
+            surface.attach(
+                Some(native.buffers[target.scene.playback.current()].wl_buffer()),
+                0,
+                0,
+            );
+            surface.damage_buffer(0, 0, width as i32, height as i32);
+
+            // The viewport takes over the scaling of the output
+            surface.set_buffer_scale(1);
+            let (x, y, w, h) = target.scene.source_rect(native.size, logical_size);
+            viewport.set_source(x, y, w, h);
+            viewport.set_destination(logical_size.0 as i32, logical_size.1 as i32);
+
+            return Ok(());
+        }
+
+        // A scene shown through the viewport before may have left a source rectangle that
+        // does not fit this buffer, undo it the way the output was sized
+        if let Some(viewport) = viewport {
+            viewport.set_source(-1.0, -1.0, -1.0, -1.0);
+            if fractional {
+                viewport.set_destination(logical_size.0 as i32, logical_size.1 as i32);
+                surface.set_buffer_scale(1);
+            } else {
+                viewport.set_destination(-1, -1);
+                surface.set_buffer_scale(scale.integer());
+            }
+        }
+
+        let (width, height) = target.scene.size;
+        let stride = width as i32 * 4;
+
+        // The previous buffer is reused once the compositor released it
+        let reusable = target
+            .buffer
+            .as_ref()
+            .filter(|buffer| buffer.height() == height as i32 && buffer.stride() == stride);
+        let canvas = match reusable.and_then(|buffer| buffer.canvas(&mut self.pool)) {
+            Some(canvas) => canvas,
+            None => {
+                let (buffer, _) = self
+                    .pool
+                    .create_buffer(
+                        width as i32,
+                        height as i32,
+                        stride,
+                        wl_shm::Format::Argb8888,
+                    )
+                    .wrap_err("failed to create a wl_shm buffer")?;
+                let buffer = target.buffer.insert(buffer);
+                buffer.canvas(&mut self.pool).expect("new buffers are free")
+            }
+        };
+
+        target.scene.draw(canvas);
+
+        let buffer = target.buffer.as_ref().expect("buffer was created above");
+        buffer.attach_to(surface)?;
+        surface.damage_buffer(0, 0, width as i32, height as i32);
+
+        Ok(())
+    }
+
+    /// The native frames of `path`, uploaded from `scene` unless another output already did.
+    /// Files decoded for outputs of other sizes have frames of other sizes, those are not shared.
+    /// `None` when the frames do not fit into [`NATIVE_BUFFER_LIMIT`].
+    fn upload(&mut self, path: &Path, scene: &ShmScene) -> Result<Option<Rc<NativeFrames>>> {
+        let size = scene.frames[0].dimensions();
+        let key = (path.to_owned(), size);
+        if let Some(native) = self.native.get(&key).and_then(Weak::upgrade) {
+            return Ok(Some(native));
+        }
+
+        self.native.retain(|_, native| native.strong_count() > 0);
+        let used: usize = self
+            .native
+            .values()
+            .filter_map(Weak::upgrade)
+            .map(|native| native.byte_len())
+            .sum();
+        let len = scene.frames.len() * size.0 as usize * size.1 as usize * 4;
+        if used + len > NATIVE_BUFFER_LIMIT {
+            return Ok(None);
+        }
+
+        let stride = size.0 as i32 * 4;
+        let mut buffers = Vec::with_capacity(scene.frames.len());
+        for frame in &scene.frames {
+            let (buffer, canvas) = self
+                .pool
+                .create_buffer(
+                    size.0 as i32,
+                    size.1 as i32,
+                    stride,
+                    wl_shm::Format::Argb8888,
+                )
+                .wrap_err("failed to create a wl_shm buffer")?;
+            blit(canvas, size, frame, (0, 0), false);
+            buffers.push(buffer);
+        }
+
+        let native = Rc::new(NativeFrames { buffers, size });
+        self.native.insert(key, Rc::downgrade(&native));
+        Ok(Some(native))
+    }
+}
+
+/// Software rendered wallpaper of one output, with the buffer it was last drawn into.
+pub struct ShmSurface {
+    pub scene: ShmScene,
+    buffer: Option<Buffer>,
+    /// Set once the scene is shown through the viewport instead of `buffer`.
+    native: Option<Rc<NativeFrames>>,
+}
+
+impl ShmSurface {
+    pub fn new(scene: ShmScene) -> Self {
+        Self {
+            scene,
+            buffer: None,
+            native: None,
+        }
+    }
+}
+
+/// An image or animation composited on the CPU.
+pub struct ShmScene {
+    path: Option<PathBuf>,
+    frames: Vec<RgbaImage>,
+    playback: Playback,
+    /// Frames scaled to the current size, see [`SCALED_CACHE_LIMIT`].
+    scaled: Vec<Option<RgbaImage>>,
+    fit: FitMode,
+    downscale: Downscale,
+    background: Color,
+    size: (u32, u32),
+}
+
+impl ShmScene {
+    /// Reads and decodes a wallpaper on the calling thread, the daemon decodes images with
+    /// [`SceneType::decode`] on a worker and passes them to [`ShmScene::from_decoded`].
+    pub fn load(wallpaper: &WallpaperConfig) -> Result<Self> {
+        match wallpaper.scene {
+            SceneKind::Image | SceneKind::Animated => {
+                let decoded = SceneType::decode(wallpaper, None)?;
+                Ok(Self::from_decoded(wallpaper, decoded))
+            }
+            SceneKind::Video | SceneKind::Shader | SceneKind::Slideshow => {
+                bail!("{:?} scenes need a GPU", wallpaper.scene)
+            }
+            SceneKind::None => Ok(Self::empty(wallpaper.background)),
+        }
+    }
+
+    /// Shows the result of [`SceneType::decode`].
+    pub fn from_decoded(wallpaper: &WallpaperConfig, decoded: DecodedImage) -> Self {
+        let sequence = match decoded {
+            DecodedImage::Still(img) => FrameSequence::still(img),
+            DecodedImage::Animated(sequence) => sequence,
+        };
+
+        let mut scene = Self::new(sequence, wallpaper.fit, wallpaper.background);
+        scene.path = wallpaper.path.clone();
+        scene.downscale = wallpaper.downscale;
+        scene
+    }
+
+    /// A scene showing only `background`.
+    pub fn empty(background: Color) -> Self {
+        let sequence = FrameSequence {
+            frames: Vec::new(),
+            plays: Some(1),
+        };
+        Self::new(sequence, FitMode::default(), background)
+    }
+
+    fn new(sequence: FrameSequence, fit: FitMode, background: Color) -> Self {
+        let (frames, delays): (Vec<_>, Vec<Duration>) = sequence.frames.into_iter().unzip();
+
+        Self {
+            path: None,
+            scaled: Vec::new(),
+            playback: Playback::new(delays, sequence.plays),
+            frames,
+            fit,
+            downscale: Downscale::default(),
+            background,
+            size: (1, 1),
+        }
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32) {
+        let size = (width.max(1), height.max(1));
+        if size != self.size {
+            self.size = size;
+            self.scaled.clear();
+        }
+    }
+
+    pub fn is_animated(&self) -> bool {
+        self.playback.is_playing()
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.playback.set_paused(paused);
+    }
+
+    pub fn next_update(&self) -> Option<Instant> {
+        self.playback.next_update()
+    }
+
+    pub fn update(&mut self, now: Instant) -> bool {
+        self.playback.update(now)
+    }
+
+    /// File to share between outputs when the compositor can crop and scale the frames,
+    /// which covers the modes where they cover the whole output.
+    fn viewport_path(&self) -> Option<&Path> {
+        let covers = matches!(self.fit, FitMode::Fill | FitMode::Stretch);
+        self.path
+            .as_deref()
+            .filter(|_| covers && !self.frames.is_empty())
+    }
+
+    /// Part of a frame of `image` size that is visible on an output of `target` size, as the
+    /// viewport source rectangle.
+    fn source_rect(&self, image: (u32, u32), target: (u32, u32)) -> (f64, f64, f64, f64) {
+        let (iw, ih) = (image.0 as f64, image.1 as f64);
+        if self.fit == FitMode::Stretch {
+            return (0.0, 0.0, iw, ih);
+        }
+
+        // Crop the longer side of the image to the aspect ratio of the output
+        let aspect = target.0.max(1) as f64 / target.1.max(1) as f64;
+        let (w, h) = if iw / ih > aspect {
+            (ih * aspect, ih)
+        } else {
+            (iw, iw / aspect)
+        };
+        ((iw - w) / 2.0, (ih - h) / 2.0, w, h)
+    }
+
+    /// Composites the current frame over the background into an ARGB8888 canvas of the current
+    /// size.
+    pub fn draw(&mut self, canvas: &mut [u8]) {
+        let background = premultiply([
+            self.background.r,
+            self.background.g,
+            self.background.b,
+            self.background.a,
+        ]);
+        for pixel in canvas.chunks_exact_mut(4) {
+            pixel.copy_from_slice(&background);
+        }
+
+        if self.frames.is_empty() {
+            return;
+        }
+
+        let index = self.playback.current();
+        let (x, y, _) = placement(self.fit, self.frames[index].dimensions(), self.size);
+        let tile = self.fit == FitMode::Tile;
+        let cacheable = self.scaled_len() * self.frames.len() <= SCALED_CACHE_LIMIT;
+        if self.scaled.len() != self.frames.len() {
+            self.scaled = vec![None; self.frames.len()];
+        }
+
+        match &self.scaled[index] {
+            Some(scaled) => blit(canvas, self.size, scaled, (x, y), tile),
+            None => {
+                let scaled = self.scale(&self.frames[index]);
+                blit(canvas, self.size, &scaled, (x, y), tile);
+                if cacheable {
+                    self.scaled[index] = Some(scaled);
+                }
+            }
+        }
+    }
+
+    /// Bytes of a frame scaled to the current size.
+    fn scaled_len(&self) -> usize {
+        let (width, height) = placement(self.fit, self.frames[0].dimensions(), self.size).2;
+        width as usize * height as usize * 4
+    }
+
+    fn scale(&self, frame: &RgbaImage) -> RgbaImage {
+        let (_, _, (width, height)) = placement(self.fit, frame.dimensions(), self.size);
+        if (width, height) == frame.dimensions() {
+            frame.clone()
+        } else {
+            resample(frame, (width, height), self.downscale)
+        }
+    }
+}
+
+/// Copies the scaled `image` into the canvas with its top left corner at `offset`, or repeated
+/// over the whole canvas when tiling. Like the GPU path, its alpha replaces the background
+/// instead of blending with it.
+fn blit(canvas: &mut [u8], target: (u32, u32), image: &RgbaImage, offset: (i64, i64), tile: bool) {
+    let (iw, ih) = (image.width() as i64, image.height() as i64);
+
+    for (ty, row) in canvas.chunks_exact_mut(target.0 as usize * 4).enumerate() {
+        for (tx, out) in row.chunks_exact_mut(4).enumerate() {
+            let (tx, ty) = (tx as i64, ty as i64);
+            let (sx, sy) = if tile {
+                (tx % iw, ty % ih)
+            } else {
+                (tx - offset.0, ty - offset.1)
+            };
+            if (0..iw).contains(&sx) && (0..ih).contains(&sy) {
+                out.copy_from_slice(&premultiply(image.get_pixel(sx as u32, sy as u32).0));
+            }
+        }
+    }
+}
+
+/// RGBA to the premultiplied, little endian ARGB of `wl_shm`.
+fn premultiply([r, g, b, a]: [u8; 4]) -> [u8; 4] {
+    let mul = |c: u8| ((c as u16 * a as u16 + 127) / 255) as u8;
+    [mul(b), mul(g), mul(r), a]
+}
@@ -0,0 +1,538 @@
+use std::{
+    collections::hash_map::RandomState,
+    fs,
+    hash::{BuildHasher, Hasher},
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver, TryRecvError},
+    thread,
+    time::{Duration, Instant},
+};
+
+use color_eyre::eyre::{bail, eyre, Result, WrapErr};
+use image::DynamicImage;
+use smithay_client_toolkit::reexports::calloop::{
+    channel::{self, Channel, Sender},
+    LoopHandle,
+};
+use wayland_client::QueueHandle;
+
+use super::cache::ImageCache;
+use super::engine::{EngineCore, EngineShell, SceneType};
+use super::image_scene::{decode_still, ImageScene};
+use crate::config::{Downscale, FitMode, SceneKind, WallpaperConfig};
+
+/// Order in which the images of a slideshow are shown. Directories are listed again for every
+/// round, so images added in the meantime show up.
+pub struct Playlist {
+    sources: Vec<PathBuf>,
+    shuffle: bool,
+    rng: u64,
+    files: Vec<PathBuf>,
+    position: usize,
+    /// The round after this one, fixed as soon as it is peeked into.
+    next_round: Option<Vec<PathBuf>>,
+}
+
+impl Playlist {
+    pub fn new(sources: Vec<PathBuf>, shuffle: bool) -> Result<Self> {
+        let mut playlist = Self {
+            sources,
+            shuffle,
+            // Only needs to differ between runs
+            rng: RandomState::new().build_hasher().finish() | 1,
+            files: Vec::new(),
+            position: 0,
+            next_round: None,
+        };
+        playlist.files = playlist.round()?;
+
+        Ok(playlist)
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn current(&self) -> &Path {
+        &self.files[self.position]
+    }
+
+    /// The file `step` places away from the current one, without moving there.
+    pub fn peek(&mut self, step: isize) -> PathBuf {
+        match self.index(step) {
+            (index, false) => self.files[index].clone(),
+            (index, true) => self.upcoming_round()[index].clone(),
+        }
+    }
+
+    /// Moves `step` places, into the next round when going past the last file. Going back
+    /// before the first file wraps around within the round.
+    pub fn step(&mut self, step: isize) -> PathBuf {
+        let (index, next_round) = self.index(step);
+        if next_round {
+            self.upcoming_round();
+            self.files = self.next_round.take().unwrap_or_default();
+        }
+        self.position = index;
+
+        self.current().to_owned()
+    }
+
+    /// Index `step` places away, and whether it is in the next round.
+    fn index(&mut self, step: isize) -> (usize, bool) {
+        let target = self.position as isize + step;
+        let len = self.files.len() as isize;
+        if target < len {
+            return (target.rem_euclid(len) as usize, false);
+        }
+
+        let round = self.upcoming_round().len() as isize;
+        ((target - len).rem_euclid(round) as usize, true)
+    }
+
+    fn upcoming_round(&mut self) -> &[PathBuf] {
+        if self.next_round.is_none() {
+            let round = self.round().unwrap_or_else(|err| {
+                log::warn!("Failed to list the slideshow again, repeating it: {err:#}");
+                self.files.clone()
+            });
+            self.next_round = Some(round);
+        }
+
+        self.next_round.as_deref().unwrap_or(&self.files)
+    }
+
+    fn round(&mut self) -> Result<Vec<PathBuf>> {
+        let mut files = Vec::new();
+        for source in &self.sources {
+            if source.is_dir() {
+                files.extend(images_in(source)?);
+            } else {
+                files.push(source.clone());
+            }
+        }
+        if files.is_empty() {
+            bail!("the slideshow has no images");
+        }
+
+        if self.shuffle {
+            // Fisher-Yates with xorshift, the order does not need to be any good
+            for i in (1..files.len()).rev() {
+                self.rng ^= self.rng << 13;
+                self.rng ^= self.rng >> 7;
+                self.rng ^= self.rng << 17;
+                files.swap(i, (self.rng % (i as u64 + 1)) as usize);
+            }
+            // The last image of a round is not shown again right away
+            if files.len() > 1 && files.first() == self.files.last() {
+                let last = files.len() - 1;
+                files.swap(0, last);
+            }
+        }
+
+        Ok(files)
+    }
+}
+
+/// Still images directly inside `dir`, sorted by name.
+fn images_in(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files: Vec<PathBuf> = fs::read_dir(dir)
+        .wrap_err_with(|| format!("failed to list {}", dir.display()))?
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| {
+            path.is_file()
+                && SceneKind::for_path(path) == SceneKind::Image
+                && image::ImageFormat::from_path(path).is_ok()
+        })
+        .collect();
+    files.sort();
+
+    Ok(files)
+}
+
+/// Draws the outputs whose slideshow finished decoding an image, woken by the decoder threads
+/// of every [`SlideshowScene`] given the returned sender.
+pub fn bind(
+    loop_handle: &LoopHandle<'static, EngineShell>,
+    qh: &QueueHandle<EngineShell>,
+) -> Result<Sender<()>> {
+    let (sender, decoded): (Sender<()>, Channel<()>) = channel::channel();
+
+    let qh = qh.clone();
+    loop_handle
+        .insert_source(decoded, move |event, _, shell| {
+            if let channel::Event::Msg(()) = event {
+                for index in 0..shell.outputs.len() {
+                    let SceneType::SlideshowBackground(scene) = &mut shell.outputs[index].scene
+                    else {
+                        continue;
+                    };
+                    if scene.is_decoded() {
+                        shell.draw_output(&qh, index);
+                    }
+                }
+            }
+        })
+        .map_err(|err| eyre!(err.error))?;
+
+    Ok(sender)
+}
+
+/// Shows the images of a [`Playlist`] one after the other. Images are decoded on worker
+/// threads, the next one halfway through the current one, so neither switching nor stepping
+/// through the slideshow waits for the decoder.
+pub struct SlideshowScene {
+    playlist: Playlist,
+    /// `None` until the first image is decoded, only the background is drawn until then.
+    current: Option<ImageScene>,
+    /// The image that replaces `current` once it is decoded.
+    pending: Option<Pending>,
+    preloaded: Option<Decoding>,
+    style: ImageStyle,
+    interval: Duration,
+    /// Size of the output, images are shrunk to it when decoded. Unknown until the first
+    /// resize.
+    size: Option<(u32, u32)>,
+
+    shown_at: Option<Instant>,
+    preload_at: Option<Instant>,
+    paused: bool,
+    /// Wakes the event loop once an image is decoded, see [`bind`]. Without one, the scene is
+    /// redrawn on every frame until the image is ready.
+    waker: Option<Sender<()>>,
+}
+
+/// An image of the playlist that is on its way to the screen.
+struct Pending {
+    decoding: Decoding,
+    /// Places to move on when the image fails to load.
+    skip: isize,
+    /// Images of the playlist tried so far, to give up once all of them failed.
+    attempt: usize,
+    /// Whether the interval starts over once the image is shown.
+    restart_timer: bool,
+}
+
+impl SlideshowScene {
+    pub fn new(wallpaper: &WallpaperConfig) -> Result<Self> {
+        let playlist = Playlist::new(wallpaper.slideshow_sources(), wallpaper.shuffle)?;
+
+        // The first image is decoded once the size is known, see `resize`
+        Ok(Self {
+            playlist,
+            current: None,
+            pending: None,
+            preloaded: None,
+            style: ImageStyle {
+                fit: wallpaper.fit,
+                downscale: wallpaper.downscale,
+                background: wallpaper.background.into(),
+                cache: wallpaper.cache,
+            },
+            // Zero would switch on every frame
+            interval: Duration::from_secs(wallpaper.interval.max(1)),
+            size: None,
+            shown_at: None,
+            preload_at: None,
+            paused: false,
+            waker: None,
+        })
+    }
+
+    /// Has the decoder threads wake the event loop once they are done, instead of checking on
+    /// them every frame.
+    pub fn set_waker(&mut self, waker: Sender<()>) {
+        self.waker = Some(waker);
+    }
+
+    pub fn background(&self) -> wgpu::Color {
+        self.style.background
+    }
+
+    /// The file that is showing, or about to.
+    pub fn current_path(&self) -> &Path {
+        self.playlist.current()
+    }
+
+    pub fn resize(&mut self, core: &EngineCore, width: u32, height: u32) {
+        let stale = match self.size {
+            // Shrunk for the old size
+            Some((old_width, old_height)) => width > old_width || height > old_height,
+            // Nothing was decoded yet
+            None => true,
+        };
+        self.size = Some((width, height));
+
+        if stale {
+            // Decoded again without starting the interval over
+            let restart_timer = self
+                .pending
+                .as_ref()
+                .is_some_and(|pending| pending.restart_timer);
+            self.preloaded = None;
+            if let Err(err) = self.request(0, restart_timer) {
+                log::warn!("Failed to decode the slideshow for the new size: {err:#}");
+            }
+        }
+        if let Some(current) = &mut self.current {
+            current.resize(core, width, height);
+        }
+    }
+
+    /// Whether the scene changes over time. While an image is decoded, only when there is no
+    /// waker to show it as soon as it is ready.
+    pub fn is_playing(&self) -> bool {
+        match self.pending {
+            Some(_) => self.waker.is_none(),
+            None => !self.paused && self.playlist.len() > 1,
+        }
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+        // The current image gets its full interval again once resumed
+        self.shown_at = None;
+    }
+
+    /// Moves `step` places through the playlist, e.g. when asked over the control socket. The
+    /// image there replaces the current one once it is decoded.
+    pub fn step(&mut self, step: isize) -> Result<()> {
+        self.request(step, true)
+    }
+
+    /// Whether the next update shows another image, one that finished decoding.
+    pub fn switches(&mut self) -> bool {
+        self.current.is_some() && self.is_decoded()
+    }
+
+    /// Whether the image on its way is ready to be shown, or failed to load.
+    pub fn is_decoded(&mut self) -> bool {
+        self.pending
+            .as_mut()
+            .is_some_and(|pending| pending.decoding.is_done())
+    }
+
+    /// Whether the next image is due at `now`.
+    fn is_due(&self, now: Instant) -> bool {
+        !self.paused
+            && self.playlist.len() > 1
+            && self
+                .shown_at
+                .is_some_and(|shown_at| now.duration_since(shown_at) >= self.interval)
+    }
+
+    pub fn next_update(&self) -> Option<Instant> {
+        // Checked on every frame until the decoder is done, unless it wakes the event loop
+        if self.pending.is_some() && self.waker.is_none() {
+            return None;
+        }
+        let switch_at = self.shown_at? + self.interval;
+        Some(self.preload_at.map_or(switch_at, |at| at.min(switch_at)))
+    }
+
+    pub fn update(&mut self, core: &EngineCore, now: Instant) -> bool {
+        let shown = self.show_decoded(core);
+        if self.shown_at.is_none() {
+            if self.current.is_some() {
+                self.shown_at = Some(now);
+                self.preload_at = Some(now + self.interval / 2);
+            }
+            return shown;
+        }
+
+        // Images missed while e.g. the output was off are skipped, not caught up on
+        if self.pending.is_none() && self.is_due(now) {
+            if let Err(err) = self.request(1, false) {
+                log::error!("Failed to switch the slideshow: {err:?}");
+            }
+            self.shown_at = Some(now);
+            self.preload_at = Some(now + self.interval / 2);
+            return shown;
+        }
+
+        if self.preload_at.is_some_and(|at| now >= at) {
+            self.preload_at = None;
+            self.preload();
+        }
+        shown
+    }
+
+    /// Blocks on the channel of the decoder thread until the image on its way is shown, trying
+    /// the next images while they fail to load.
+    pub fn wait_for_decoder(&mut self, core: &EngineCore) {
+        while let Some(pending) = &mut self.pending {
+            pending.decoding.wait();
+            self.show_decoded(core);
+        }
+    }
+
+    /// Moves `step` places through the playlist and starts decoding the image there.
+    fn request(&mut self, step: isize, restart_timer: bool) -> Result<()> {
+        self.start_decoding(step, 1, restart_timer)
+    }
+
+    fn start_decoding(&mut self, step: isize, attempt: usize, restart_timer: bool) -> Result<()> {
+        let path = self.playlist.step(step);
+        let decoding = match self.preloaded.take() {
+            Some(mut preloaded) if preloaded.path == path => {
+                // Its worker woke the event loop while nothing was waiting for it yet
+                if preloaded.is_done() {
+                    if let Some(waker) = &self.waker {
+                        let _ = waker.send(());
+                    }
+                }
+                preloaded
+            }
+            _ => Decoding::start(path, self.size, self.style, self.waker.clone())?,
+        };
+        self.pending = Some(Pending {
+            decoding,
+            // Images that fail to load are skipped in the same direction
+            skip: if step == 0 { 1 } else { step },
+            attempt,
+            restart_timer,
+        });
+
+        Ok(())
+    }
+
+    /// Uploads and shows the pending image once the worker is done with it. Returns whether
+    /// another image is shown now.
+    fn show_decoded(&mut self, core: &EngineCore) -> bool {
+        let done = self
+            .pending
+            .as_mut()
+            .is_some_and(|pending| pending.decoding.is_done());
+        if !done {
+            return false;
+        }
+        let pending = self.pending.take().expect("checked above");
+
+        let path = pending.decoding.path.clone();
+        let style = self.style;
+        let scene = pending.decoding.finish().and_then(|img| {
+            ImageScene::new(core, &img, style.fit, style.downscale, style.background)
+        });
+        let mut scene = match scene {
+            Ok(scene) => scene,
+            Err(err) => {
+                log::warn!("Skipping {} in the slideshow: {err:#}", path.display());
+                if pending.attempt >= self.playlist.len() {
+                    log::error!("None of the images of the slideshow could be loaded");
+                } else if let Err(err) =
+                    self.start_decoding(pending.skip, pending.attempt + 1, pending.restart_timer)
+                {
+                    log::error!("Failed to switch the slideshow: {err:?}");
+                }
+                return false;
+            }
+        };
+
+        if let Some((width, height)) = self.size {
+            scene.resize(core, width, height);
+        }
+        self.current = Some(scene);
+        if pending.restart_timer {
+            // The timer starts over with the next update
+            self.shown_at = None;
+            self.preload_at = None;
+        }
+        true
+    }
+
+    /// Starts decoding the next image, it is uploaded once it is due.
+    fn preload(&mut self) {
+        let path = self.playlist.peek(1);
+        match Decoding::start(path.clone(), self.size, self.style, self.waker.clone()) {
+            Ok(decoding) => self.preloaded = Some(decoding),
+            // Decoded when it is due instead
+            Err(err) => log::warn!("Failed to preload {}: {err:#}", path.display()),
+        }
+    }
+
+    pub fn draw<'a>(&'a self, core: &'a EngineCore, pass: &mut wgpu::RenderPass<'a>) {
+        if let Some(current) = &self.current {
+            current.draw(core, pass);
+        }
+    }
+}
+
+/// An image decoded on a worker thread.
+struct Decoding {
+    path: PathBuf,
+    receiver: Receiver<Result<DynamicImage>>,
+    /// What the worker sent, once it is done.
+    decoded: Option<Result<DynamicImage>>,
+}
+
+impl Decoding {
+    fn start(
+        path: PathBuf,
+        size: Option<(u32, u32)>,
+        style: ImageStyle,
+        waker: Option<Sender<()>>,
+    ) -> Result<Self> {
+        let (sender, receiver) = mpsc::channel();
+        let decode_path = path.clone();
+        thread::Builder::new()
+            .name("aphrodite-slideshow".into())
+            .spawn(move || {
+                let _ = sender.send(decode(&decode_path, size, style));
+                // The event loop is gone when the daemon is shutting down
+                if let Some(waker) = waker {
+                    let _ = waker.send(());
+                }
+            })
+            .wrap_err("failed to start the decoder thread")?;
+
+        Ok(Self {
+            path,
+            receiver,
+            decoded: None,
+        })
+    }
+
+    /// Whether the worker is done, without waiting for it.
+    fn is_done(&mut self) -> bool {
+        if self.decoded.is_none() {
+            match self.receiver.try_recv() {
+                Ok(decoded) => self.decoded = Some(decoded),
+                Err(TryRecvError::Empty) => {}
+                Err(TryRecvError::Disconnected) => {
+                    self.decoded = Some(Err(eyre!("the decoder thread stopped")));
+                }
+            }
+        }
+        self.decoded.is_some()
+    }
+
+    fn wait(&mut self) {
+        if self.decoded.is_none() {
+            let decoded = self.receiver.recv();
+            self.decoded =
+                Some(decoded.unwrap_or_else(|_| Err(eyre!("the decoder thread stopped"))));
+        }
+    }
+
+    fn finish(mut self) -> Result<DynamicImage> {
+        self.wait();
+        self.decoded.take().expect("waited for the worker above")
+    }
+}
+
+/// How every image of a slideshow is drawn.
+#[derive(Clone, Copy)]
+struct ImageStyle {
+    fit: FitMode,
+    downscale: Downscale,
+    background: wgpu::Color,
+    cache: bool,
+}
+
+/// Decodes an image, shrunk to `size` when known.
+fn decode(path: &Path, size: Option<(u32, u32)>, style: ImageStyle) -> Result<DynamicImage> {
+    let bytes = fs::read(path).wrap_err_with(|| format!("failed to read {}", path.display()))?;
+    let cache = style.cache.then(ImageCache::open).flatten();
+    decode_still(&bytes, style.fit, style.downscale, size, cache.as_ref())
+        .wrap_err_with(|| format!("failed to load {}", path.display()))
+}
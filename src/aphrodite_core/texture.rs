@@ -1,7 +1,12 @@
 use std::num::NonZeroU32;
 
 use anyhow::*;
-use image::GenericImageView;
+use image::imageops::FilterType;
+use wgpu::util::DeviceExt;
+
+use super::engine::{quad_pipeline, EngineCore};
+use super::image_scene::fit_quad;
+use crate::config::FitMode;
 
 pub struct Texture {
     pub texture: wgpu::Texture,
@@ -10,25 +15,45 @@ pub struct Texture {
 }
 
 impl Texture {
-    pub fn from_bytes(
-        device: &wgpu::Device,
-        queue: &wgpu::Queue,
-        bytes: &[u8],
-        label: &str,
-    ) -> Result<Self> {
+    pub fn from_bytes(core: &EngineCore, bytes: &[u8], label: &str) -> Result<Self> {
         let img = image::load_from_memory(bytes)?;
-        Self::from_image(device, queue, &img, Some(label))
+        Self::from_image(core, &img, Some(label))
     }
 
+    /// Uploads an image with a full mip chain, so it can be drawn smaller than it is without
+    /// aliasing. Images larger than the device supports are downscaled to fit first.
     pub fn from_image(
-        device: &wgpu::Device,
-        queue: &wgpu::Queue,
+        core: &EngineCore,
         img: &image::DynamicImage,
         label: Option<&str>,
     ) -> Result<Self> {
-        let rgba = img.to_rgba8();
-        let dimensions = img.dimensions();
+        let limit = core.device.limits().max_texture_dimension_2d;
+        // Keeps the aspect ratio, the quad is placed with the size of the original
+        let rgba = match shrink_to_limit(img, limit, label) {
+            Some(scaled) => scaled.to_rgba8(),
+            None => img.to_rgba8(),
+        };
+        let dimensions = rgba.dimensions();
+
+        let texture =
+            Self::with_mip_levels(&core.device, dimensions, mip_level_count(dimensions), label);
+        texture.write(&core.queue, &rgba);
+        core.mipmaps.generate(core, &texture.texture);
+
+        Ok(texture)
+    }
+
+    /// Creates an uninitialized RGBA texture, to be filled with [`Texture::write`].
+    pub fn new(device: &wgpu::Device, dimensions: (u32, u32), label: Option<&str>) -> Self {
+        Self::with_mip_levels(device, dimensions, 1, label)
+    }
 
+    fn with_mip_levels(
+        device: &wgpu::Device,
+        dimensions: (u32, u32),
+        mip_level_count: u32,
+        label: Option<&str>,
+    ) -> Self {
         let size = wgpu::Extent3d {
             width: dimensions.0,
             height: dimensions.1,
@@ -38,45 +63,208 @@ impl Texture {
         let texture = device.create_texture(&wgpu::TextureDescriptor {
             label,
             size,
-            mip_level_count: 1,
+            mip_level_count,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
             format,
-            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            // Mip levels are rendered from a copy of the first one
+            usage: wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_DST
+                | wgpu::TextureUsages::COPY_SRC
+                | wgpu::TextureUsages::RENDER_ATTACHMENT,
             view_formats: &[],
         });
 
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        Self {
+            texture,
+            view,
+            sampler,
+        }
+    }
+
+    /// Replaces the first mip level with tightly packed RGBA pixels.
+    pub fn write(&self, queue: &wgpu::Queue, rgba: &[u8]) {
+        let size = self.texture.size();
+
         queue.write_texture(
             wgpu::ImageCopyTexture {
                 aspect: wgpu::TextureAspect::All,
-                texture: &texture,
+                texture: &self.texture,
                 mip_level: 0,
                 origin: wgpu::Origin3d::ZERO,
             },
-            &rgba,
+            rgba,
             wgpu::ImageDataLayout {
                 offset: 0,
-                bytes_per_row: Some(4 * dimensions.0),
-                rows_per_image: Some(dimensions.1),
+                bytes_per_row: Some(4 * size.width),
+                rows_per_image: Some(size.height),
             },
             size,
         );
+    }
+}
+
+/// Levels down to 1x1 pixel.
+pub fn mip_level_count(dimensions: (u32, u32)) -> u32 {
+    dimensions.0.max(dimensions.1).max(1).ilog2() + 1
+}
+
+/// Fills the mip levels of a texture on the GPU, each halving the one above with a box filter.
+pub struct MipmapGenerator {
+    pipeline: wgpu::RenderPipeline,
+    sampler: wgpu::Sampler,
+    /// Stretches the level above over the whole target.
+    vertex_buffer: wgpu::Buffer,
+}
+
+impl MipmapGenerator {
+    /// `layout` and `shader` are those of the image pipeline, which already draws a texture
+    /// onto a quad.
+    pub fn new(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        shader: &wgpu::ShaderModule,
+    ) -> Self {
+        // The format of every texture made by `Texture::new`
+        let pipeline = quad_pipeline(
+            device,
+            wgpu::TextureFormat::Rgba8UnormSrgb,
+            "Mipmap Render Pipeline",
+            layout,
+            shader,
+        );
 
-        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        // Sampling halfway between four texels of the level above averages them
         let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-            address_mode_u: wgpu::AddressMode::ClampToEdge,
-            address_mode_v: wgpu::AddressMode::ClampToEdge,
-            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            label: Some("Mipmap"),
             mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Nearest,
-            mipmap_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Linear,
             ..Default::default()
         });
 
-        Ok(Self {
-            texture,
-            view,
+        let vertices = fit_quad(FitMode::Stretch, (1, 1), (1, 1));
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Mipmap quad"),
+            contents: bytemuck::cast_slice(&vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+
+        Self {
+            pipeline,
             sampler,
-        })
+            vertex_buffer,
+        }
+    }
+
+    /// Renders every level after the first from the one above it. Sampling one level of a
+    /// texture while rendering into another is a feedback loop on the GL backend, so every
+    /// level is also rendered into a scratch texture that the next one is sampled from.
+    pub fn generate(&self, core: &EngineCore, texture: &wgpu::Texture) {
+        let scratch = |size| {
+            core.device.create_texture(&wgpu::TextureDescriptor {
+                label: Some("Mipmap"),
+                size,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: texture.format(),
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                    | wgpu::TextureUsages::TEXTURE_BINDING
+                    | wgpu::TextureUsages::COPY_DST,
+                view_formats: &[],
+            })
+        };
+
+        let mut encoder = core.device.create_command_encoder(&Default::default());
+        let first = scratch(texture.size());
+        encoder.copy_texture_to_texture(
+            texture.as_image_copy(),
+            first.as_image_copy(),
+            texture.size(),
+        );
+        let mut source = first.create_view(&wgpu::TextureViewDescriptor::default());
+
+        for level in 1..texture.mip_level_count() {
+            let bind_group = core.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("Mipmap"),
+                layout: &core.image_bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&source),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&self.sampler),
+                    },
+                ],
+            });
+
+            let next = scratch(
+                texture
+                    .size()
+                    .mip_level_size(level, wgpu::TextureDimension::D2),
+            )
+            .create_view(&wgpu::TextureViewDescriptor::default());
+            let level = texture.create_view(&wgpu::TextureViewDescriptor {
+                base_mip_level: level,
+                mip_level_count: Some(1),
+                ..Default::default()
+            });
+            for target in [&level, &next] {
+                let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Mipmap"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view: target,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                            store: true,
+                        },
+                    })],
+                    depth_stencil_attachment: None,
+                });
+                pass.set_pipeline(&self.pipeline);
+                pass.set_bind_group(0, &bind_group, &[]);
+                pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+                pass.draw(0..6, 0..1);
+            }
+            source = next;
+        }
+        core.queue.submit(Some(encoder.finish()));
     }
-}
\ No newline at end of file
+}
+
+/// Shrinks an image larger than `limit` pixels on a side to fit, keeping the aspect ratio.
+/// `None` when it fits as it is.
+pub fn shrink_to_limit(
+    img: &image::DynamicImage,
+    limit: u32,
+    label: Option<&str>,
+) -> Option<image::DynamicImage> {
+    if img.width() <= limit && img.height() <= limit {
+        return None;
+    }
+
+    let scaled = img.resize(limit, limit, FilterType::Triangle);
+    log::warn!(
+        "{} is {}x{}, larger than the {limit} pixels the GPU supports, downscaling it to {}x{}",
+        label.unwrap_or("Image"),
+        img.width(),
+        img.height(),
+        scaled.width(),
+        scaled.height()
+    );
+    Some(scaled)
+}
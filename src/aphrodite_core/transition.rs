@@ -0,0 +1,232 @@
+//! Animated blends from the previous wallpaper of an output into the next one.
+//!
+//! The last frame of the old wallpaper is kept in a texture. While the transition runs, the new
+//! scene is drawn into a second texture every frame, so animated wallpapers keep playing, and
+//! `transition.wgsl` blends both onto the output.
+
+use std::time::{Duration, Instant};
+
+use wgpu::util::DeviceExt;
+
+use super::engine::{EngineCore, SceneType, SimpleImage};
+use super::image_scene::fit_quad;
+use crate::config::{Easing, FitMode, TransitionKind, WallpaperConfig};
+
+/// Mirrors `Uniforms` in `transition.wgsl`, including its padding.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct TransitionUniforms {
+    progress: f32,
+    effect: u32,
+    aspect: f32,
+    _padding: u32,
+}
+
+pub struct Transition {
+    /// Keeps the last frame of the old wallpaper alive for `from_bind_group`.
+    _from: wgpu::Texture,
+    from_bind_group: wgpu::BindGroup,
+    /// The new scene is drawn here first.
+    _to: wgpu::Texture,
+    to_view: wgpu::TextureView,
+    to_bind_group: wgpu::BindGroup,
+    uniform_buffer: wgpu::Buffer,
+    vertex_buffer: wgpu::Buffer,
+
+    uniforms: TransitionUniforms,
+    easing: Easing,
+    duration: Duration,
+    started: Option<Instant>,
+}
+
+impl Transition {
+    /// Layout of the second bind group of the transition pipeline, the first one is the
+    /// layout of [`SimpleImage`].
+    pub fn bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        let mut entries = SimpleImage::image_layout_entries().to_vec();
+        entries.push(wgpu::BindGroupLayoutEntry {
+            binding: 2,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        });
+
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Transition"),
+            entries: &entries,
+        })
+    }
+
+    /// Starts a transition away from what `scene` shows right now, or what `previous` shows
+    /// when a transition is already running. `size` is the size of the output in pixels.
+    pub fn new(
+        core: &EngineCore,
+        scene: &SceneType,
+        previous: Option<&Transition>,
+        size: (u32, u32),
+        wallpaper: &WallpaperConfig,
+    ) -> Self {
+        let from = frame_texture(core, size, "Transition from");
+        let from_view = from.create_view(&wgpu::TextureViewDescriptor::default());
+        match previous {
+            Some(previous) => previous.draw(core, &from_view, scene),
+            None => core.draw_scene(&from_view, scene),
+        }
+        let to = frame_texture(core, size, "Transition to");
+        let to_view = to.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let uniforms = TransitionUniforms {
+            progress: 0.0,
+            effect: effect_index(wallpaper.transition),
+            aspect: size.0.max(1) as f32 / size.1.max(1) as f32,
+            _padding: 0,
+        };
+        let uniform_buffer = core
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Transition uniforms"),
+                contents: bytemuck::bytes_of(&uniforms),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            });
+
+        // Both textures have the size of the output, so they are never scaled
+        let sampler = SimpleImage::create_sampler(core, FitMode::Stretch);
+        let from_bind_group = core.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Transition from"),
+            layout: &core.transition_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&from_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+            ],
+        });
+        let to_bind_group = core.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Transition to"),
+            layout: &core.image_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&to_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
+        });
+
+        let vertices = fit_quad(FitMode::Stretch, (1, 1), (1, 1));
+        let vertex_buffer = core
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Transition quad"),
+                contents: bytemuck::cast_slice(&vertices),
+                usage: wgpu::BufferUsages::VERTEX,
+            });
+
+        Self {
+            _from: from,
+            from_bind_group,
+            _to: to,
+            to_view,
+            to_bind_group,
+            uniform_buffer,
+            vertex_buffer,
+            uniforms,
+            easing: wallpaper.transition_easing,
+            duration: Duration::from_secs_f32(wallpaper.transition_duration.max(0.0)),
+            started: None,
+        }
+    }
+
+    /// Whether the new wallpaper is fully visible.
+    pub fn is_done(&self) -> bool {
+        self.started.is_some() && self.uniforms.progress >= 1.0
+    }
+
+    /// Advances the transition to `now`, it starts with the first update.
+    pub fn update(&mut self, core: &EngineCore, now: Instant) {
+        let started = *self.started.get_or_insert(now);
+        let elapsed = now.duration_since(started).as_secs_f32();
+        let linear = if self.duration.is_zero() {
+            1.0
+        } else {
+            elapsed / self.duration.as_secs_f32()
+        };
+
+        // Eased progress never overshoots, so the end is still exactly 1
+        self.uniforms.progress = self.easing.apply(linear);
+        core.queue
+            .write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&self.uniforms));
+    }
+
+    /// Draws `scene` blended with the old wallpaper into `view`.
+    pub fn draw(&self, core: &EngineCore, view: &wgpu::TextureView, scene: &SceneType) {
+        core.draw_scene(&self.to_view, scene);
+
+        let mut encoder = core.device.create_command_encoder(&Default::default());
+        {
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Transition"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+
+            pass.set_pipeline(&core.transition_render_pipeline);
+            pass.set_bind_group(0, &self.to_bind_group, &[]);
+            pass.set_bind_group(1, &self.from_bind_group, &[]);
+            pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+            pass.draw(0..6, 0..1);
+        }
+        core.queue.submit(Some(encoder.finish()));
+    }
+}
+
+/// Index of the effect in `transition.wgsl`.
+fn effect_index(kind: TransitionKind) -> u32 {
+    match kind {
+        TransitionKind::None | TransitionKind::Crossfade => 0,
+        TransitionKind::Wipe => 1,
+        TransitionKind::Circle => 2,
+        TransitionKind::Pixelate => 3,
+        TransitionKind::Dissolve => 4,
+    }
+}
+
+/// A texture in the format of the pipelines, to render a whole frame into and sample it later.
+fn frame_texture(core: &EngineCore, size: (u32, u32), label: &str) -> wgpu::Texture {
+    core.device.create_texture(&wgpu::TextureDescriptor {
+        label: Some(label),
+        size: wgpu::Extent3d {
+            width: size.0.max(1),
+            height: size.1.max(1),
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: core.format,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        view_formats: &[],
+    })
+}
@@ -0,0 +1,92 @@
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+}
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+) -> VertexOutput {
+    var out: VertexOutput;
+    out.tex_coords = model.tex_coords;
+    out.clip_position = vec4<f32>(model.position, 1.0);
+    return out;
+}
+
+// Fragment shader
+
+// Mirrors `TransitionUniforms` in transition.rs
+struct Uniforms {
+    progress: f32,
+    effect: u32,
+    // Width divided by height of the output
+    aspect: f32,
+    _padding: u32,
+}
+
+// The new wallpaper, with the layout of the image pipeline
+@group(0) @binding(0)
+var t_to: texture_2d<f32>;
+@group(0) @binding(1)
+var s_to: sampler;
+
+// The last frame of the old wallpaper
+@group(1) @binding(0)
+var t_from: texture_2d<f32>;
+@group(1) @binding(1)
+var s_from: sampler;
+@group(1) @binding(2)
+var<uniform> transition: Uniforms;
+
+// Width of the soft edge of the wipe and circle effects
+const EDGE: f32 = 0.05;
+
+fn hash(p: vec2<f32>) -> f32 {
+    return fract(sin(dot(p, vec2<f32>(12.9898, 78.233))) * 43758.5453);
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let p = transition.progress;
+    var coords = in.tex_coords;
+    // How much of the new wallpaper shows
+    var amount = p;
+
+    switch transition.effect {
+        // Wipe
+        case 1u: {
+            amount = smoothstep(0.0, EDGE, p * (1.0 + EDGE) - coords.x);
+        }
+        // Circle
+        case 2u: {
+            let scale = vec2<f32>(transition.aspect, 1.0);
+            let radius = p * (length(scale * 0.5) + EDGE);
+            amount = smoothstep(0.0, EDGE, radius - length((coords - 0.5) * scale));
+        }
+        // Pixelate, the blocks are largest halfway through
+        case 3u: {
+            let strength = 1.0 - abs(p * 2.0 - 1.0);
+            if strength > 0.0 {
+                let cells = vec2<f32>(transition.aspect, 1.0) * mix(256.0, 12.0, strength);
+                coords = (floor(coords * cells) + 0.5) / cells;
+            }
+            amount = smoothstep(0.4, 0.6, p);
+        }
+        // Dissolve
+        case 4u: {
+            amount = step(hash(floor(in.clip_position.xy)), p);
+        }
+        // Crossfade
+        default: {}
+    }
+
+    // Sampled outside of the branches, which keeps the control flow uniform
+    let old_color = textureSample(t_from, s_from, coords);
+    let new_color = textureSample(t_to, s_to, coords);
+    return mix(old_color, new_color, amount);
+}
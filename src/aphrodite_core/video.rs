@@ -0,0 +1,308 @@
+use std::{
+    io::{self, Read},
+    path::Path,
+    process::{Child, ChildStdout, Command, Stdio},
+    sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TryRecvError},
+    thread,
+    time::{Duration, Instant},
+};
+
+use color_eyre::eyre::{bail, eyre, Result, WrapErr};
+use wgpu::util::DeviceExt;
+
+use super::engine::{EngineCore, SimpleImage, Vertex};
+use super::image_scene::fit_quad;
+use super::texture;
+use crate::config::FitMode;
+
+/// Decoder binaries, looked up in `$PATH`.
+const FFMPEG: &str = "ffmpeg";
+const FFPROBE: &str = "ffprobe";
+
+/// Frames decoded ahead. Once they are queued the pipe fills up and ffmpeg blocks, which is
+/// also what keeps a paused video from using any CPU.
+const QUEUED_FRAMES: usize = 2;
+
+/// How long to wait for the first frame before giving up on a video.
+const FIRST_FRAME_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Used when the container does not report a frame rate.
+const FALLBACK_FPS: f64 = 30.0;
+
+#[derive(Debug, Clone, Copy)]
+pub struct VideoInfo {
+    pub width: u32,
+    pub height: u32,
+    pub fps: f64,
+}
+
+/// Reads the size and frame rate of the first video stream with ffprobe.
+pub fn probe(path: &Path) -> Result<VideoInfo> {
+    let output = Command::new(FFPROBE)
+        .args(["-v", "error", "-select_streams", "v:0"])
+        .args(["-show_entries", "stream=width,height,avg_frame_rate"])
+        .args(["-of", "csv=p=0"])
+        .arg(path)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|err| missing_binary(FFPROBE, err))?;
+
+    if !output.status.success() {
+        bail!(
+            "{FFPROBE} failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut fields = stdout.trim().split(',');
+    let (Some(width), Some(height), rate) = (fields.next(), fields.next(), fields.next()) else {
+        bail!("{} has no video stream", path.display());
+    };
+
+    // Rates are fractions like 30000/1001, and 0/0 when unknown
+    let fps = rate
+        .and_then(|rate| rate.split_once('/'))
+        .and_then(|(num, den)| Some(num.parse::<f64>().ok()? / den.parse::<f64>().ok()?))
+        .filter(|fps| fps.is_finite() && *fps > 0.0)
+        .unwrap_or(FALLBACK_FPS);
+
+    Ok(VideoInfo {
+        width: width.parse().wrap_err("invalid video width")?,
+        height: height.parse().wrap_err("invalid video height")?,
+        fps,
+    })
+}
+
+fn missing_binary(name: &str, err: io::Error) -> color_eyre::Report {
+    if err.kind() == io::ErrorKind::NotFound {
+        eyre!("{name} not found in $PATH, install ffmpeg to use video wallpapers")
+    } else {
+        eyre!(err).wrap_err(format!("failed to start {name}"))
+    }
+}
+
+/// An ffmpeg process writing raw RGBA frames, read on a separate thread.
+struct VideoDecoder {
+    child: Child,
+    frames: Receiver<Vec<u8>>,
+}
+
+impl VideoDecoder {
+    /// Starts decoding `path` in an endless loop, at `fps` frames per second if given.
+    fn spawn(path: &Path, info: VideoInfo, fps: Option<f64>) -> Result<Self> {
+        let mut command = Command::new(FFMPEG);
+        command
+            .args(["-nostdin", "-v", "error", "-stream_loop", "-1"])
+            // ffprobe reports the stored size, so the frames must not be rotated
+            .args(["-noautorotate", "-i"])
+            .arg(path)
+            .arg("-an");
+        if let Some(fps) = fps {
+            command.args(["-vf", &format!("fps={fps}")]);
+        }
+        let mut child = command
+            .args(["-f", "rawvideo", "-pix_fmt", "rgba", "-"])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|err| missing_binary(FFMPEG, err))?;
+
+        let stdout = child.stdout.take().expect("stdout is piped");
+        let frame_len = info.width as usize * info.height as usize * 4;
+        let (sender, frames) = mpsc::sync_channel(QUEUED_FRAMES);
+        thread::Builder::new()
+            .name("video decoder".into())
+            .spawn(move || read_frames(stdout, frame_len, sender))?;
+
+        Ok(Self { child, frames })
+    }
+}
+
+fn read_frames(mut stdout: ChildStdout, frame_len: usize, sender: SyncSender<Vec<u8>>) {
+    loop {
+        let mut frame = vec![0; frame_len];
+        if let Err(err) = stdout.read_exact(&mut frame) {
+            if err.kind() != io::ErrorKind::UnexpectedEof {
+                log::error!("Failed to read a video frame: {err}");
+            }
+            return;
+        }
+        // The scene is gone
+        if sender.send(frame).is_err() {
+            return;
+        }
+    }
+}
+
+impl Drop for VideoDecoder {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// A looping video, uploaded frame by frame into a single texture.
+pub struct VideoScene {
+    decoder: VideoDecoder,
+    texture: texture::Texture,
+    bind_group: wgpu::BindGroup,
+    vertex_buffer: wgpu::Buffer,
+    video_size: (u32, u32),
+    fit: FitMode,
+    pub background: wgpu::Color,
+
+    frame_interval: Duration,
+    next_frame: Option<Instant>,
+    /// When the decoder was started, until the first frame arrives. Only the background is
+    /// drawn until then.
+    waiting_since: Option<Instant>,
+    paused: bool,
+    ended: bool,
+}
+
+impl VideoScene {
+    /// Starts the decoder, the first frame is shown by the update it arrives before. `fps` caps
+    /// the frame rate of the video.
+    pub fn new(
+        core: &EngineCore,
+        path: &Path,
+        fit: FitMode,
+        background: wgpu::Color,
+        fps: Option<u32>,
+    ) -> Result<Self> {
+        let info = probe(path)?;
+        let cap = fps.map(f64::from).filter(|&cap| cap > 0.0 && cap < info.fps);
+        let decoder = VideoDecoder::spawn(path, info, cap)?;
+
+        let video_size = (info.width, info.height);
+        let texture = texture::Texture::new(&core.device, video_size, Some("Video frame"));
+
+        let sampler = SimpleImage::create_sampler(core, fit);
+        let bind_group = SimpleImage::create_bind_group(core, &texture, &sampler);
+
+        let vertex_buffer = core
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Video quad"),
+                contents: bytemuck::cast_slice(&[Vertex::default(); 6]),
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            });
+
+        Ok(Self {
+            decoder,
+            texture,
+            bind_group,
+            vertex_buffer,
+            video_size,
+            fit,
+            background,
+            frame_interval: Duration::from_secs_f64(1.0 / cap.unwrap_or(info.fps)),
+            next_frame: None,
+            waiting_since: Some(Instant::now()),
+            paused: false,
+            ended: false,
+        })
+    }
+
+    pub fn resize(&self, core: &EngineCore, width: u32, height: u32) {
+        let vertices = fit_quad(self.fit, self.video_size, (width, height));
+        core.queue
+            .write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(&vertices));
+    }
+
+    /// Whether the scene changes over time. Also while paused before the first frame, to show
+    /// it as soon as it arrives.
+    pub fn is_playing(&self) -> bool {
+        !self.ended && (!self.paused || self.waiting_since.is_some())
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+        // Resume from the current frame instead of catching up on the paused time
+        self.next_frame = None;
+    }
+
+    pub fn next_update(&self) -> Option<Instant> {
+        self.next_frame
+    }
+
+    /// Uploads the next frame once it is due. Returns whether the texture changed.
+    pub fn update(&mut self, core: &EngineCore, now: Instant) -> bool {
+        if !self.is_playing() || self.next_frame.is_some_and(|next| now < next) {
+            return false;
+        }
+
+        let frame = match self.decoder.frames.try_recv() {
+            Ok(frame) => frame,
+            Err(TryRecvError::Empty) => {
+                if self
+                    .waiting_since
+                    .is_some_and(|since| now.duration_since(since) >= FIRST_FRAME_TIMEOUT)
+                {
+                    log::error!("{FFMPEG} did not produce a frame in time");
+                    self.ended = true;
+                }
+                // The decoder fell behind, try again on the next callback
+                return false;
+            }
+            Err(TryRecvError::Disconnected) => {
+                if self.waiting_since.is_some() {
+                    log::error!("{FFMPEG} could not decode the video");
+                } else {
+                    log::error!("{FFMPEG} stopped, the video wallpaper stays on its last frame");
+                }
+                self.ended = true;
+                return false;
+            }
+        };
+        self.show(core, &frame, now);
+
+        true
+    }
+
+    /// Blocks until the reader thread got the first frame from ffmpeg and it is shown, or until
+    /// ffmpeg exits or misses [`FIRST_FRAME_TIMEOUT`].
+    pub fn wait_for_first_frame(&mut self, core: &EngineCore) {
+        let Some(since) = self.waiting_since.filter(|_| !self.ended) else {
+            return;
+        };
+
+        let timeout = FIRST_FRAME_TIMEOUT.saturating_sub(since.elapsed());
+        match self.decoder.frames.recv_timeout(timeout) {
+            Ok(frame) => self.show(core, &frame, Instant::now()),
+            Err(RecvTimeoutError::Timeout) => {
+                log::error!("{FFMPEG} did not produce a frame in time");
+                self.ended = true;
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                log::error!("{FFMPEG} could not decode the video");
+                self.ended = true;
+            }
+        }
+    }
+
+    /// Uploads a frame received at `now` and schedules the next one.
+    fn show(&mut self, core: &EngineCore, frame: &[u8], now: Instant) {
+        self.texture.write(&core.queue, frame);
+        self.waiting_since = None;
+
+        let next = self.next_frame.unwrap_or(now) + self.frame_interval;
+        // More than a frame behind, e.g. after the output was off: start over from now
+        self.next_frame = Some(if next < now {
+            now + self.frame_interval
+        } else {
+            next
+        });
+    }
+
+    pub fn draw<'a>(&'a self, core: &'a EngineCore, pass: &mut wgpu::RenderPass<'a>) {
+        if self.waiting_since.is_some() {
+            return;
+        }
+        pass.set_pipeline(&core.image_render_pipeline);
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        pass.draw(0..6, 0..1);
+    }
+}
@@ -0,0 +1,189 @@
+//! Command-line client for the control socket of a running `aphrodite run`.
+
+use std::{
+    io::{BufRead, BufReader, Write},
+    os::unix::net::UnixStream,
+    path::PathBuf,
+    process::ExitCode,
+    time::Duration,
+};
+
+use clap::{Args, Parser, Subcommand};
+use color_eyre::eyre::{bail, Result, WrapErr};
+use ipc::{OutputStatus, Request, Response};
+
+#[path = "../ipc.rs"]
+mod ipc;
+
+/// Loading a large wallpaper happens before the daemon answers.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Parser, Debug)]
+#[command(
+    name = "aphroditectl",
+    version,
+    about = "Control a running aphrodite daemon"
+)]
+struct Cli {
+    /// Print the response of the daemon as JSON
+    #[arg(short, long, global = true)]
+    json: bool,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Show another file
+    Set {
+        /// Image or scene file to display
+        path: PathBuf,
+
+        #[command(flatten)]
+        target: Target,
+
+        /// Scene type as in the config file, picked from the extension by default
+        #[arg(short, long)]
+        scene: Option<String>,
+
+        /// Fit mode as in the config file, the current one is kept by default
+        #[arg(short, long)]
+        fit: Option<String>,
+    },
+    /// Freeze animated wallpapers
+    Pause(Target),
+    /// Continue animated wallpapers
+    Resume(Target),
+    /// Show the next image of a slideshow, or the next file in the directory of the wallpaper
+    Next(Target),
+    /// Show the previous image of a slideshow, or the previous file in the directory
+    Previous(Target),
+    /// Describe the wallpaper of every output
+    Status,
+    /// List the names of the outputs
+    Outputs,
+    /// Stop the daemon
+    Quit,
+}
+
+#[derive(Args, Debug)]
+struct Target {
+    /// Name of the output, e.g. `DP-1`. Every output by default
+    #[arg(short, long)]
+    output: Option<String>,
+}
+
+fn main() -> Result<ExitCode> {
+    color_eyre::install()?;
+    let cli = Cli::parse();
+    let names_only = matches!(cli.command, Command::Outputs);
+
+    let request = match cli.command {
+        Command::Set {
+            path,
+            target,
+            scene,
+            fit,
+        } => Request::Set {
+            output: target.output,
+            // The daemon resolves relative paths against its own directory
+            path: std::path::absolute(&path)
+                .wrap_err_with(|| format!("invalid path {}", path.display()))?,
+            scene,
+            fit,
+        },
+        Command::Pause(target) => Request::Pause {
+            output: target.output,
+        },
+        Command::Resume(target) => Request::Resume {
+            output: target.output,
+        },
+        Command::Next(target) => Request::Next {
+            output: target.output,
+        },
+        Command::Previous(target) => Request::Previous {
+            output: target.output,
+        },
+        Command::Status | Command::Outputs => Request::Query,
+        Command::Quit => Request::Quit,
+    };
+    let response = send(&request)?;
+
+    if cli.json {
+        let json = match &response {
+            Response::State { outputs } if names_only => {
+                let names: Vec<_> = outputs.iter().map(|output| &output.name).collect();
+                serde_json::to_string(&names)?
+            }
+            _ => serde_json::to_string(&response)?,
+        };
+        println!("{json}");
+    } else {
+        match &response {
+            Response::State { outputs } if names_only => {
+                for output in outputs {
+                    println!("{}", output.name.as_deref().unwrap_or("unknown"));
+                }
+            }
+            Response::State { outputs } => {
+                for output in outputs {
+                    println!("{}", describe(output));
+                }
+            }
+            Response::Error { message } => eprintln!("error: {message}"),
+            Response::Ok => {}
+        }
+    }
+
+    Ok(match response {
+        Response::Error { .. } => ExitCode::FAILURE,
+        _ => ExitCode::SUCCESS,
+    })
+}
+
+/// Sends one request and waits for its response.
+fn send(request: &Request) -> Result<Response> {
+    let path = ipc::socket_path()?;
+    let mut stream = UnixStream::connect(&path).wrap_err_with(|| {
+        format!(
+            "failed to connect to {}, is `aphrodite run` running?",
+            path.display()
+        )
+    })?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+
+    let mut line = serde_json::to_vec(request)?;
+    line.push(b'\n');
+    stream.write_all(&line)?;
+
+    let mut line = String::new();
+    BufReader::new(stream)
+        .read_line(&mut line)
+        .wrap_err("failed to read the response")?;
+    if line.is_empty() {
+        bail!("the daemon closed the connection without answering");
+    }
+
+    serde_json::from_str(&line).wrap_err("invalid response")
+}
+
+/// `DP-1: image /home/me/wall.png (fill, 2560x1440, gpu)`
+fn describe(output: &OutputStatus) -> String {
+    let mut line = format!(
+        "{}: {}",
+        output.name.as_deref().unwrap_or("unknown"),
+        output.scene
+    );
+    if let Some(path) = &output.path {
+        line += &format!(" {}", path.display());
+    }
+    line += &format!(
+        " ({}, {}x{}, {}",
+        output.fit, output.width, output.height, output.renderer
+    );
+    if output.paused {
+        line += ", paused";
+    }
+    line + ")"
+}
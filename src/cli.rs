@@ -0,0 +1,122 @@
+use std::{fmt, path::PathBuf, str::FromStr};
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use serde::Deserialize;
+use smithay_client_toolkit::shell::wlr_layer::Layer;
+
+#[derive(Parser, Debug)]
+#[command(
+    name = "aphrodite",
+    version,
+    about = "GPU wallpaper engine for Wayland"
+)]
+pub struct Cli {
+    /// Config file to use instead of `$XDG_CONFIG_HOME/aphrodite/config.toml`
+    #[arg(short, long, global = true)]
+    pub config: Option<PathBuf>,
+
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Run the wallpaper daemon on the layer shell
+    Run(WallpaperArgs),
+    /// Show the wallpaper in a regular window
+    Preview(WallpaperArgs),
+    /// Validate the wallpaper source and the compositor without drawing anything
+    Check(WallpaperArgs),
+    /// Render a single frame of the wallpaper into an image file
+    Render {
+        #[command(flatten)]
+        wallpaper: WallpaperArgs,
+
+        /// Where to write the rendered frame (format is taken from the extension)
+        out: PathBuf,
+    },
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct WallpaperArgs {
+    /// Image or scene file to display
+    #[arg(short, long)]
+    pub image: Option<PathBuf>,
+
+    /// Name of the output to put the wallpaper on, e.g. `DP-1`
+    #[arg(short, long)]
+    pub output: Option<String>,
+
+    /// Layer-shell layer of the wallpaper surface
+    #[arg(short, long, value_enum)]
+    pub layer: Option<LayerArg>,
+
+    /// Surface size as `WIDTHxHEIGHT`
+    #[arg(short, long)]
+    pub size: Option<Size>,
+}
+
+#[derive(ValueEnum, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LayerArg {
+    Background,
+    Bottom,
+    Top,
+    Overlay,
+}
+
+impl From<LayerArg> for Layer {
+    fn from(layer: LayerArg) -> Self {
+        match layer {
+            LayerArg::Background => Layer::Background,
+            LayerArg::Bottom => Layer::Bottom,
+            LayerArg::Top => Layer::Top,
+            LayerArg::Overlay => Layer::Overlay,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub struct Size {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Size {
+    pub const fn new(width: u32, height: u32) -> Self {
+        Self { width, height }
+    }
+}
+
+impl fmt::Display for Size {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}x{}", self.width, self.height)
+    }
+}
+
+impl FromStr for Size {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (width, height) = s
+            .split_once(['x', 'X'])
+            .ok_or_else(|| format!("expected WIDTHxHEIGHT, got `{s}`"))?;
+
+        let parse = |v: &str| {
+            v.trim()
+                .parse::<u32>()
+                .map_err(|e| format!("invalid dimension `{v}`: {e}"))
+        };
+
+        Ok(Size::new(parse(width)?, parse(height)?))
+    }
+}
+
+impl TryFrom<String> for Size {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
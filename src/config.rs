@@ -0,0 +1,504 @@
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use color_eyre::eyre::{bail, Result, WrapErr};
+use serde::{Deserialize, Serialize};
+use smithay_client_toolkit::shell::wlr_layer::Anchor;
+
+use crate::cli::{LayerArg, Size, WallpaperArgs};
+
+/// Contents of `$XDG_CONFIG_HOME/aphrodite/config.toml`.
+///
+/// ```toml
+/// renderer = "auto"
+///
+/// [default]
+/// scene = "image"
+/// path = "~/Pictures/wall.png"
+/// fit = "fit"
+/// background = "#1e1e2e"
+/// downscale = "lanczos"
+/// transition = "crossfade"
+/// transition-duration = 0.8
+///
+/// [[output]]
+/// name = "DP-1"
+/// scene = "image"
+/// path = "~/Pictures/left.png"
+/// layer = "bottom"
+/// anchor = ["top", "left"]
+/// size = "800x600"
+/// fps = 30
+///
+/// [[output]]
+/// name = "HDMI-A-1"
+/// path = "~/Pictures/walls"
+/// interval = 600
+/// shuffle = true
+/// ```
+///
+/// `[default]` is used for every output that has no `[[output]]` entry of its own. A directory
+/// as the `path`, or a `playlist` of files and directories, makes a slideshow.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    pub renderer: Renderer,
+    #[serde(default)]
+    pub default: WallpaperConfig,
+    #[serde(default, rename = "output")]
+    pub outputs: Vec<OutputConfig>,
+}
+
+/// How the wallpapers are drawn.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Renderer {
+    /// wgpu, or shared memory when no adapter can be found
+    #[default]
+    Auto,
+    /// wgpu only, failing without an adapter
+    Gpu,
+    /// Draw on the CPU into wl_shm buffers. Images and animated images only, no slideshows.
+    Shm,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct OutputConfig {
+    pub name: String,
+    #[serde(flatten)]
+    pub wallpaper: WallpaperConfig,
+    /// Keys that are not wallpaper settings. serde ignores `deny_unknown_fields` on flattened
+    /// structs, so they are collected here and rejected by [`OutputConfig::normalize`].
+    #[serde(flatten)]
+    unknown: BTreeMap<String, toml::Value>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct WallpaperConfig {
+    pub scene: SceneKind,
+    pub path: Option<PathBuf>,
+    pub fit: FitMode,
+    /// Color around the image in the `fit` and `center` modes
+    pub background: Color,
+    /// Filter for images drawn smaller than they are
+    pub downscale: Downscale,
+    /// Keep images prepared for an output in `$XDG_CACHE_HOME/aphrodite`, so the next start
+    /// does not decode them again
+    pub cache: bool,
+    pub layer: LayerArg,
+    pub anchor: Vec<AnchorEdge>,
+    /// Fixed surface size, by default the compositor sizes the surface to the anchored edges
+    pub size: Option<Size>,
+    /// Frame rate cap for animated scenes, which otherwise follow the refresh rate of the output
+    pub fps: Option<u32>,
+    /// Images and directories of a slideshow, used instead of `path` when given
+    pub playlist: Vec<PathBuf>,
+    /// Seconds each image of a slideshow is shown
+    pub interval: u64,
+    /// Show the images of a slideshow in random order instead of sorted by name
+    pub shuffle: bool,
+    /// Effect blending into a new wallpaper, e.g. the next image of a slideshow. GPU only
+    pub transition: TransitionKind,
+    /// Length of the transition in seconds
+    pub transition_duration: f32,
+    pub transition_easing: Easing,
+}
+
+impl Default for WallpaperConfig {
+    fn default() -> Self {
+        Self {
+            scene: SceneKind::None,
+            path: None,
+            fit: FitMode::default(),
+            background: Color::default(),
+            downscale: Downscale::default(),
+            cache: true,
+            layer: LayerArg::Background,
+            anchor: vec![
+                AnchorEdge::Top,
+                AnchorEdge::Bottom,
+                AnchorEdge::Left,
+                AnchorEdge::Right,
+            ],
+            size: None,
+            fps: None,
+            playlist: Vec::new(),
+            interval: 300,
+            shuffle: false,
+            transition: TransitionKind::None,
+            transition_duration: 1.0,
+            transition_easing: Easing::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SceneKind {
+    Image,
+    /// GIF, APNG or animated WebP
+    #[serde(alias = "gif")]
+    Animated,
+    /// Any video ffmpeg can decode, looped
+    Video,
+    /// WGSL fragment shader
+    Shader,
+    /// Still images from a directory or a playlist, one after the other
+    #[serde(alias = "playlist")]
+    Slideshow,
+    #[default]
+    None,
+}
+
+impl SceneKind {
+    /// Scene to use for a path given without an explicit scene.
+    pub fn for_path(path: &Path) -> Self {
+        if path.is_dir() {
+            return SceneKind::Slideshow;
+        }
+
+        let ext = path.extension().and_then(|ext| ext.to_str());
+        match ext.map(str::to_ascii_lowercase).as_deref() {
+            Some("gif" | "apng") => SceneKind::Animated,
+            Some("mp4" | "m4v" | "mkv" | "webm" | "mov" | "avi") => SceneKind::Video,
+            Some("wgsl") => SceneKind::Shader,
+            _ => SceneKind::Image,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FitMode {
+    /// Cover the whole output, cropping what does not fit
+    #[default]
+    Fill,
+    /// Show the whole image, letterboxed
+    Fit,
+    /// Keep the native size in the middle of the output
+    Center,
+    /// Ignore the aspect ratio and cover the output
+    Stretch,
+    /// Repeat the image at its native size
+    Tile,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TransitionKind {
+    /// Hard cut
+    #[default]
+    None,
+    Crossfade,
+    /// The new wallpaper slides in from the left
+    Wipe,
+    /// The new wallpaper grows from the center in a circle
+    Circle,
+    /// The old wallpaper dissolves into ever larger blocks, the new one out of them
+    Pixelate,
+    /// The new wallpaper appears pixel by pixel in random order
+    Dissolve,
+}
+
+/// Speed curve of a transition.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Easing {
+    Linear,
+    EaseIn,
+    EaseOut,
+    #[default]
+    EaseInOut,
+}
+
+impl Easing {
+    /// Maps linear progress in `0..=1` onto the curve.
+    pub fn apply(self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t * t,
+            Easing::EaseOut => 1.0 - (1.0 - t).powi(3),
+            Easing::EaseInOut => t * t * (3.0 - 2.0 * t),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Downscale {
+    /// Mipmaps sampled by the GPU, fast and usually good enough
+    #[default]
+    Mipmap,
+    /// Resample still images on the CPU whenever the output size changes, the sharpest
+    Lanczos,
+    /// Resample still images on the CPU by averaging, softer but free of ringing
+    Box,
+}
+
+/// An sRGB color written as `#rrggbb` or `#rrggbbaa`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8,
+}
+
+impl Default for Color {
+    /// Opaque black, a transparent background would show whatever is below the layer.
+    fn default() -> Self {
+        Color {
+            r: 0,
+            g: 0,
+            b: 0,
+            a: 255,
+        }
+    }
+}
+
+impl FromStr for Color {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex = s.strip_prefix('#').unwrap_or(s);
+        if !matches!(hex.len(), 6 | 8) || !hex.is_ascii() {
+            return Err(format!("expected #rrggbb or #rrggbbaa, got `{s}`"));
+        }
+
+        let channel = |i: usize| {
+            u8::from_str_radix(&hex[i..i + 2], 16).map_err(|e| format!("invalid color `{s}`: {e}"))
+        };
+        let a = if hex.len() == 8 { channel(6)? } else { 255 };
+
+        Ok(Color {
+            r: channel(0)?,
+            g: channel(2)?,
+            b: channel(4)?,
+            a,
+        })
+    }
+}
+
+impl TryFrom<String> for Color {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Color> for wgpu::Color {
+    /// Clear colors are given in linear space, the surfaces are sRGB.
+    fn from(color: Color) -> Self {
+        let linear = |c: u8| {
+            let c = c as f64 / 255.0;
+            if c <= 0.04045 {
+                c / 12.92
+            } else {
+                ((c + 0.055) / 1.055).powf(2.4)
+            }
+        };
+
+        wgpu::Color {
+            r: linear(color.r),
+            g: linear(color.g),
+            b: linear(color.b),
+            a: color.a as f64 / 255.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AnchorEdge {
+    Top,
+    Bottom,
+    Left,
+    Right,
+}
+
+impl Config {
+    /// Loads the config from `path`, or from the XDG location when no path is given.
+    ///
+    /// A missing file in the XDG location is not an error, it just yields the default config.
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let (path, required) = match path {
+            Some(path) => (path.to_path_buf(), true),
+            None => match Self::default_path() {
+                Some(path) => (path, false),
+                None => return Ok(Self::default()),
+            },
+        };
+
+        if !required && !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let text = fs::read_to_string(&path)
+            .wrap_err_with(|| format!("failed to read config {}", path.display()))?;
+        let mut config: Config = toml::from_str(&text)
+            .wrap_err_with(|| format!("failed to parse config {}", path.display()))?;
+
+        config
+            .default
+            .normalize()
+            .wrap_err_with(|| format!("invalid [default] in {}", path.display()))?;
+        for output in &mut config.outputs {
+            output.normalize().wrap_err_with(|| {
+                format!("invalid output `{}` in {}", output.name, path.display())
+            })?;
+        }
+
+        Ok(config)
+    }
+
+    pub fn default_path() -> Option<PathBuf> {
+        let base = match env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+        };
+
+        Some(base.join("aphrodite").join("config.toml"))
+    }
+
+    /// Wallpaper for the output with the given name, falling back to `[default]`.
+    pub fn wallpaper_for(&self, output: Option<&str>) -> &WallpaperConfig {
+        output
+            .and_then(|name| self.outputs.iter().find(|o| o.name == name))
+            .map(|o| &o.wallpaper)
+            .unwrap_or(&self.default)
+    }
+}
+
+impl WallpaperConfig {
+    /// Applies the command-line flags on top of the configured values.
+    pub fn apply_args(&mut self, args: &WallpaperArgs) {
+        if let Some(path) = &args.image {
+            self.path = Some(path.clone());
+            if self.scene == SceneKind::None {
+                self.scene = SceneKind::for_path(path);
+            }
+        }
+        if let Some(layer) = args.layer {
+            self.layer = layer;
+        }
+        if args.size.is_some() {
+            self.size = args.size;
+        }
+    }
+
+    pub fn anchor(&self) -> Anchor {
+        self.anchor
+            .iter()
+            .fold(Anchor::empty(), |anchor, edge| anchor | Anchor::from(*edge))
+    }
+
+    /// Rejects sizes the compositor can not fill in: a width or height of zero, which is the
+    /// default, is only valid when both edges along it are anchored.
+    pub fn check_size(&self) -> Result<()> {
+        let size = self.size.unwrap_or(Size::new(0, 0));
+        let anchor = self.anchor();
+        if size.width == 0 && !anchor.contains(Anchor::LEFT | Anchor::RIGHT) {
+            bail!("a width of 0 needs both the left and right edges anchored");
+        }
+        if size.height == 0 && !anchor.contains(Anchor::TOP | Anchor::BOTTOM) {
+            bail!("a height of 0 needs both the top and bottom edges anchored");
+        }
+
+        Ok(())
+    }
+
+    /// Expands a leading `~` in the paths and picks the scene of a bare `path` from its
+    /// extension, or of a bare `playlist`. Fails on a size the compositor can not fill in.
+    fn normalize(&mut self) -> Result<()> {
+        for path in self.path.iter_mut().chain(&mut self.playlist) {
+            if let (Ok(rest), Some(home)) = (path.strip_prefix("~"), env::var_os("HOME")) {
+                *path = PathBuf::from(home).join(rest);
+            }
+        }
+
+        if self.scene == SceneKind::None {
+            if !self.playlist.is_empty() {
+                self.scene = SceneKind::Slideshow;
+            } else if let Some(path) = &self.path {
+                self.scene = SceneKind::for_path(path);
+            }
+        }
+
+        self.check_size()
+    }
+
+    /// Entries of a slideshow, each a file or a directory.
+    pub fn slideshow_sources(&self) -> Vec<PathBuf> {
+        if self.playlist.is_empty() {
+            self.path.iter().cloned().collect()
+        } else {
+            self.playlist.clone()
+        }
+    }
+}
+
+impl OutputConfig {
+    fn normalize(&mut self) -> Result<()> {
+        if !self.unknown.is_empty() {
+            let keys: Vec<_> = self.unknown.keys().map(|key| format!("`{key}`")).collect();
+            bail!("unknown field {}", keys.join(", "));
+        }
+        self.wallpaper.normalize()
+    }
+}
+
+impl From<AnchorEdge> for Anchor {
+    fn from(edge: AnchorEdge) -> Self {
+        match edge {
+            AnchorEdge::Top => Anchor::TOP,
+            AnchorEdge::Bottom => Anchor::BOTTOM,
+            AnchorEdge::Left => Anchor::LEFT,
+            AnchorEdge::Right => Anchor::RIGHT,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Loads a config file with the given contents.
+    fn load(name: &str, text: &str) -> Result<Config> {
+        let path = env::temp_dir().join(format!("aphrodite-{}-{name}.toml", std::process::id()));
+        fs::write(&path, text).unwrap();
+        let config = Config::load(Some(&path));
+        fs::remove_file(&path).unwrap();
+        config
+    }
+
+    #[test]
+    fn misspelled_default_key() {
+        let text = "[default]\nscene = \"image\"\nfitt = \"fill\"\n";
+        let message = format!("{:#}", load("default", text).unwrap_err());
+        assert!(message.contains("unknown field `fitt`"), "{message}");
+    }
+
+    #[test]
+    fn misspelled_output_key() {
+        let text = "[[output]]\nname = \"DP-1\"\nscene = \"image\"\nfitt = \"fill\"\n";
+        let message = format!("{:#}", load("output", text).unwrap_err());
+        assert!(message.contains("invalid output `DP-1`"), "{message}");
+        assert!(message.contains("unknown field `fitt`"), "{message}");
+    }
+
+    #[test]
+    fn known_output_keys() {
+        let text = "[[output]]\nname = \"DP-1\"\nscene = \"none\"\nbackground = \"#102030\"\n";
+        let config = load("known", text).unwrap();
+        assert_eq!(config.outputs[0].wallpaper.scene, SceneKind::None);
+    }
+}
@@ -0,0 +1,81 @@
+//! Protocol of the control socket of a running daemon.
+//!
+//! A client connects to [`socket_path`], writes one [`Request`] as a single line of JSON and
+//! reads one [`Response`] line back, after which the daemon closes the connection.
+//!
+//! ```json
+//! {"command": "set", "output": "DP-1", "path": "/home/me/Pictures/wall.png", "fit": "fill"}
+//! {"status": "ok"}
+//! ```
+
+use std::{env, path::PathBuf};
+
+use color_eyre::eyre::{eyre, Result};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "kebab-case")]
+pub enum Request {
+    /// Shows another file, on every output unless one is named. `scene` and `fit` take the
+    /// values of the config file, the scene is picked from the extension when left out.
+    Set {
+        output: Option<String>,
+        path: PathBuf,
+        scene: Option<String>,
+        fit: Option<String>,
+    },
+    /// Freezes animated wallpapers.
+    Pause {
+        output: Option<String>,
+    },
+    Resume {
+        output: Option<String>,
+    },
+    /// Shows the next image of a slideshow, or the next file in the directory of any other
+    /// wallpaper.
+    Next {
+        output: Option<String>,
+    },
+    Previous {
+        output: Option<String>,
+    },
+    /// Describes every output, answered with [`Response::State`].
+    Query,
+    /// Exits the daemon.
+    Quit,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "kebab-case")]
+pub enum Response {
+    Ok,
+    State { outputs: Vec<OutputStatus> },
+    Error { message: String },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutputStatus {
+    pub name: Option<String>,
+    pub scene: String,
+    /// The image that is showing for slideshows
+    pub path: Option<PathBuf>,
+    pub fit: String,
+    pub paused: bool,
+    /// Buffer size in pixels
+    pub width: u32,
+    pub height: u32,
+    /// `gpu` or `shm`
+    pub renderer: String,
+}
+
+/// `$XDG_RUNTIME_DIR/aphrodite-$WAYLAND_DISPLAY.sock`, so every compositor gets its own daemon.
+pub fn socket_path() -> Result<PathBuf> {
+    let dir = env::var_os("XDG_RUNTIME_DIR")
+        .filter(|dir| !dir.is_empty())
+        .ok_or_else(|| eyre!("$XDG_RUNTIME_DIR is not set"))?;
+    let display = env::var("WAYLAND_DISPLAY").unwrap_or_else(|_| "wayland-0".into());
+    // WAYLAND_DISPLAY may also be an absolute path
+    let display = display.rsplit('/').next().unwrap_or(&display);
+
+    Ok(PathBuf::from(dir).join(format!("aphrodite-{display}.sock")))
+}
@@ -1,80 +1,193 @@
-use aphrodite_core::engine::{DisplayHandle, EngineShell};
-use color_eyre::eyre::Result;
-use raw_window_handle::{
-    RawDisplayHandle, RawWindowHandle, WaylandDisplayHandle, WaylandWindowHandle,
-};
-use smithay_client_toolkit::shell::wlr_layer::Layer;
-use smithay_client_toolkit::{
-    compositor::CompositorState,
-    output::OutputState,
-    registry::RegistryState,
-    seat::SeatState,
-    shell::{wlr_layer::LayerShell, WaylandSurface},
-};
-use wayland_client::Proxy;
-use wayland_client::{globals::registry_queue_init, Connection, QueueHandle};
+use std::{path::Path, time::Instant};
+
+use aphrodite_core::control::ControlSocket;
+use aphrodite_core::engine::{EngineCore, EngineShell, SceneType};
+use aphrodite_core::headless::OffscreenTarget;
+use aphrodite_core::slideshow::Playlist;
+use clap::Parser;
+use cli::{Cli, Command, Size, WallpaperArgs};
+use color_eyre::eyre::{bail, eyre, Result, WrapErr};
+use config::{Config, SceneKind, WallpaperConfig};
+use smithay_client_toolkit::{reexports::calloop::EventLoop, shell::wlr_layer::LayerShell};
+use wayland_client::{globals::registry_queue_init, Connection, QueueHandle, WaylandSource};
 
 mod aphrodite_core;
+mod cli;
+mod config;
+mod ipc;
 mod windowed_mode;
 
-const WINDOW: bool = false;
-
 fn main() -> Result<()> {
     env_logger::init();
     color_eyre::install()?;
 
-    if (WINDOW) {
-        pollster::block_on(windowed_mode::window::run());
-    } else {
-        let conn = Connection::connect_to_env().unwrap();
-        let (globals, mut event_queue) = registry_queue_init(&conn).unwrap();
-        let qh: QueueHandle<aphrodite_core::engine::EngineShell> = event_queue.handle();
-
-        let compositor_state =
-            CompositorState::bind(&globals, &qh).expect("wl_compositor not available");
-        let layer_shell = LayerShell::bind(&globals, &qh).unwrap();
-        let surface = compositor_state.create_surface(&qh);
-        let layer =
-            layer_shell.create_layer_surface(&qh, surface, Layer::Top, Some("Ahprodite"), None);
-
-        //Temp
-        layer.set_size(500, 500);
-        layer.commit();
-        //////
-
-        let display = {
-            let mut handle = WaylandDisplayHandle::empty();
-            handle.display = conn.backend().display_ptr() as *mut _;
-            let display_handle = RawDisplayHandle::Wayland(handle);
-
-            let mut handle = WaylandWindowHandle::empty();
-            handle.surface = layer.wl_surface().id().as_ptr() as *mut _;
-            let layer_handle = RawWindowHandle::Wayland(handle);
-
-            Some(DisplayHandle(display_handle, layer_handle))
-        };
-        let engine_core = aphrodite_core::engine::EngineCore::init_wgpu(display);
-
-        let mut engine_shell = EngineShell {
-            core: engine_core,
-            layer: layer,
-            registry_state: RegistryState::new(&globals),
-            seat_state: SeatState::new(&globals, &qh),
-            output_state: OutputState::new(&globals, &qh),
-            exit: false,
-        };
-
-        loop {
-            let a = event_queue.blocking_dispatch(&mut engine_shell).unwrap();
-            println!("Event => {a:?}");
-
-            if engine_shell.exit {
-                break;
-            }
+    let cli = Cli::parse();
+    let config = Config::load(cli.config.as_deref())?;
+
+    match cli.command {
+        Command::Run(args) => run(&config, args),
+        Command::Preview(args) => {
+            let wallpaper = resolve_wallpaper(&config, &args);
+            load_wallpaper(&wallpaper)?;
+            pollster::block_on(windowed_mode::window::run(wallpaper)).map_err(|err| eyre!(err))
+        }
+        Command::Check(args) => check(&config, args),
+        Command::Render { wallpaper, out } => render(&config, wallpaper, &out),
+    }
+}
+
+/// Configured wallpaper for the requested output with the command-line flags applied on top.
+fn resolve_wallpaper(config: &Config, args: &WallpaperArgs) -> WallpaperConfig {
+    let mut wallpaper = config.wallpaper_for(args.output.as_deref()).clone();
+    wallpaper.apply_args(args);
+    wallpaper
+}
+
+/// Reads the header of the wallpaper image, probes the video or validates the shader, if any,
+/// so a broken file is reported before a surface is created. Returns a short description of the file.
+fn load_wallpaper(wallpaper: &WallpaperConfig) -> Result<Option<String>> {
+    wallpaper.check_size()?;
+    if wallpaper.scene == SceneKind::Slideshow {
+        let playlist = Playlist::new(wallpaper.slideshow_sources(), wallpaper.shuffle)?;
+        return Ok(Some(format!("slideshow of {} images", playlist.len())));
+    }
+    let Some(path) = &wallpaper.path else {
+        return Ok(None);
+    };
+
+    if wallpaper.scene == SceneKind::Shader {
+        aphrodite_core::shader::validate(path)?;
+        return Ok(Some("WGSL shader".into()));
+    }
+
+    if wallpaper.scene == SceneKind::Video {
+        let info = aphrodite_core::video::probe(path)
+            .wrap_err_with(|| format!("failed to probe {}", path.display()))?;
+        return Ok(Some(format!(
+            "{}x{} video, {:.2} fps",
+            info.width, info.height, info.fps
+        )));
+    }
+
+    // Only the header, the image itself is decoded in the background, or read from the cache
+    let (width, height) = image::image_dimensions(path)
+        .wrap_err_with(|| format!("failed to load {}", path.display()))?;
+    Ok(Some(format!("{width}x{height}")))
+}
+
+fn check(config: &Config, args: WallpaperArgs) -> Result<()> {
+    let wallpapers = std::iter::once((None, resolve_wallpaper(config, &args))).chain(
+        config
+            .outputs
+            .iter()
+            .map(|output| (Some(output.name.as_str()), output.wallpaper.clone())),
+    );
+
+    for (output, wallpaper) in wallpapers {
+        if let Some(description) = load_wallpaper(&wallpaper)? {
+            // A slideshow may come from a playlist alone, without a path
+            let sources = match wallpaper.scene {
+                SceneKind::Slideshow => wallpaper.slideshow_sources(),
+                _ => wallpaper.path.iter().cloned().collect(),
+            };
+            let sources: Vec<_> = sources
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect();
+            println!(
+                "wallpaper [{}]: {} ({description})",
+                output.unwrap_or("default"),
+                sources.join(", "),
+            );
+        }
+    }
+
+    let conn =
+        Connection::connect_to_env().wrap_err("failed to connect to the Wayland compositor")?;
+    let (globals, event_queue) = registry_queue_init::<EngineShell>(&conn)?;
+    LayerShell::bind(&globals, &event_queue.handle())
+        .wrap_err("the compositor does not support wlr-layer-shell")?;
+    println!("compositor: wlr-layer-shell available");
+
+    let outputs = aphrodite_core::connection::list_outputs(&conn)?;
+    for (_, info) in &outputs {
+        println!(
+            "output: {} ({} {})",
+            info.name.as_deref().unwrap_or("<unnamed>"),
+            info.make,
+            info.model
+        );
+    }
+
+    if let Some(name) = &args.output {
+        if !outputs
+            .iter()
+            .any(|(_, info)| info.name.as_deref() == Some(name))
+        {
+            bail!("output `{name}` not found");
         }
+    }
 
-        drop(engine_shell.core);
-        drop(engine_shell.layer);
+    Ok(())
+}
+
+/// Renders the first frame of the wallpaper offscreen and saves it, no compositor needed.
+fn render(config: &Config, args: WallpaperArgs, out: &Path) -> Result<()> {
+    let wallpaper = resolve_wallpaper(config, &args);
+    let size = wallpaper.size.unwrap_or(Size::new(1920, 1080));
+
+    let core = EngineCore::init_wgpu(None)?;
+    let mut scene = SceneType::load(&core, &wallpaper)?;
+    scene.resize(&core, size.width, size.height);
+    scene.wait_for_decoder(&core);
+    scene.update(&core, Instant::now());
+
+    let target = OffscreenTarget::new(&core, size.width, size.height);
+    core.render_offscreen(&target, &scene);
+    let img = target.read_pixels(&core)?;
+    img.save(out)
+        .wrap_err_with(|| format!("failed to write {}", out.display()))?;
+
+    log::info!(
+        "Rendered {size} with {:?}",
+        core.adapter.get_info().name
+    );
+    Ok(())
+}
+
+fn run(config: &Config, args: WallpaperArgs) -> Result<()> {
+    let wallpaper = resolve_wallpaper(config, &args);
+    load_wallpaper(&wallpaper)?;
+
+    let conn =
+        Connection::connect_to_env().wrap_err("failed to connect to the Wayland compositor")?;
+    let (globals, mut event_queue) = registry_queue_init(&conn)?;
+    let qh: QueueHandle<EngineShell> = event_queue.handle();
+
+    let mut event_loop: EventLoop<EngineShell> = EventLoop::try_new()?;
+    let mut engine_shell =
+        EngineShell::new(&globals, &qh, event_loop.handle(), config.clone(), args)?;
+
+    // Output surfaces are created from `new_output` while the initial globals are processed.
+    event_queue.roundtrip(&mut engine_shell)?;
+    if engine_shell.outputs.is_empty() {
+        match &engine_shell.overrides.output {
+            Some(name) => log::warn!("Output `{name}` not found, waiting for it to appear"),
+            None => log::warn!("No outputs found, waiting for one to appear"),
+        }
     }
+
+    WaylandSource::new(event_queue)?
+        .insert(event_loop.handle())
+        .map_err(|err| err.error)?;
+    // The wallpaper still works without one, it just can not be controlled
+    let _control = ControlSocket::bind(&event_loop.handle(), &qh)
+        .map_err(|err| log::warn!("Control socket disabled: {err:#}"))
+        .ok();
+
+    while !engine_shell.exit {
+        event_loop.dispatch(None, &mut engine_shell)?;
+    }
+
     Ok(())
 }
@@ -1,28 +1,84 @@
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
 use raw_window_handle::{HasRawDisplayHandle, HasRawWindowHandle};
 use winit::{
+    dpi::PhysicalSize,
     event::*,
     event_loop::{ControlFlow, EventLoop},
     window::WindowBuilder,
 };
 
-use crate::aphrodite_core::engine::{DisplayHandle, EngineCore};
+use crate::aphrodite_core::engine::{DisplayHandle, EngineCore, SceneType};
+use crate::cli::Size;
+use crate::config::WallpaperConfig;
 
-pub async fn run() {
+pub async fn run(wallpaper: WallpaperConfig) -> Result<()> {
+    let size = wallpaper.size.unwrap_or(Size::new(1280, 720));
     let event_loop = EventLoop::new();
-    let mut window = WindowBuilder::new().build(&event_loop).unwrap();
+    let mut window = WindowBuilder::new()
+        .with_title("Aphrodite preview")
+        .with_inner_size(PhysicalSize::new(size.width, size.height))
+        .build(&event_loop)?;
 
-    let display_handle = Some(DisplayHandle(
-        window.raw_display_handle(),
-        window.raw_window_handle(),
-    ));
+    let display_handle = DisplayHandle(window.raw_display_handle(), window.raw_window_handle());
 
-    let engine_core = EngineCore::init_wgpu(display_handle);
+    let engine_core =
+        EngineCore::init_wgpu(Some(&display_handle)).map_err(|err| anyhow!("{err:#}"))?;
+    let mut surface = engine_core
+        .create_surface(&display_handle, size.width, size.height)
+        .map_err(|err| anyhow!("{err:#}"))?;
+    let mut scene =
+        SceneType::load(&engine_core, &wallpaper).map_err(|err| anyhow!("{err:#}"))?;
+    scene.resize(&engine_core, size.width, size.height);
+    let mut paused = false;
 
     event_loop.run(move |event, _, control_flow| {
         match event {
+            Event::WindowEvent {
+                event: WindowEvent::Resized(size),
+                ..
+            } => {
+                engine_core.configure(&mut surface, size.width, size.height);
+                scene.resize(&engine_core, size.width, size.height);
+                window.request_redraw();
+            }
+            Event::WindowEvent {
+                event:
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(VirtualKeyCode::Space),
+                                ..
+                            },
+                        ..
+                    },
+                ..
+            } => {
+                paused = !paused;
+                scene.set_paused(paused);
+                window.request_redraw();
+            }
+            Event::NewEvents(StartCause::ResumeTimeReached { .. }) => {
+                window.request_redraw();
+            }
             Event::RedrawRequested(window_id) => {
+                let now = Instant::now();
                 engine_core.update();
-                engine_core.render();
+                scene.update(&engine_core, now);
+                engine_core.render(&surface, &scene, None);
+
+                // Same pacing as the layer surfaces: wait for the scene and the fps cap
+                *control_flow = if scene.is_animated() {
+                    let mut due = scene.next_update().unwrap_or(now);
+                    if let Some(fps) = wallpaper.fps.filter(|&fps| fps > 0) {
+                        due = due.max(now + Duration::from_secs_f64(1.0 / fps as f64));
+                    }
+                    ControlFlow::WaitUntil(due)
+                } else {
+                    ControlFlow::Wait
+                };
             },
             
             _ => {},
@@ -0,0 +1,11 @@
+// Shader wallpaper used by the golden-image tests, touching every uniform.
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let wave = 0.5 + 0.5 * sin(uniforms.time * 3.0 + in.tex_coords.x * 6.28);
+    let to_mouse = distance(in.clip_position.xy, uniforms.mouse) / uniforms.resolution.y;
+    let ring = step(0.2, to_mouse) * step(to_mouse, 0.3);
+    let day = uniforms.date.z / 31.0;
+    let odd = f32(uniforms.frame % 2u);
+    return vec4<f32>(in.tex_coords.x, wave, max(ring, day * 0.5 + odd * 0.1), 1.0);
+}